@@ -1,11 +1,78 @@
 use dam::context_tools::*;
+use ndarray::{NdFloat, prelude::*};
+
+/// Function applied by `Actfn` to every element, may capture parameters or keep state
+pub trait Activation<T>: Send + Sync {
+    fn apply(&mut self, x: T) -> T;
+
+    /// Cycles from an element in to its result out
+    fn latency(&self) -> u64 {
+        1
+    }
+}
+
+impl<T, F> Activation<T> for F
+where
+    F: FnMut(T) -> T + Send + Sync,
+{
+    fn apply(&mut self, x: T) -> T {
+        self(x)
+    }
+}
+
+/// Common activations, each with the latency of a pipelined unit evaluating it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Builtin {
+    /// Tanh approximation, `0.5 x (1 + tanh(sqrt(2 / pi) (x + 0.044715 x^3)))`
+    Gelu,
+    /// `x sigmoid(x)`
+    Silu,
+    Tanh,
+    Sigmoid,
+    /// `x relu6(x + 3) / 6`
+    HardSwish,
+}
+
+impl Builtin {
+    pub fn eval<E: NdFloat>(&self, x: E) -> E {
+        let c = |v: f64| E::from(v).unwrap();
+        let sigmoid = |v: E| E::one() / (E::one() + (-v).exp());
+        match self {
+            Builtin::Gelu => {
+                let inner = c((2.0 / std::f64::consts::PI).sqrt()) * (x + c(0.044715) * x * x * x);
+                c(0.5) * x * (E::one() + inner.tanh())
+            }
+            Builtin::Silu => x * sigmoid(x),
+            Builtin::Tanh => x.tanh(),
+            Builtin::Sigmoid => sigmoid(x),
+            Builtin::HardSwish => x * (x + c(3.0)).max(E::zero()).min(c(6.0)) / c(6.0),
+        }
+    }
+}
+
+impl<E: NdFloat> Activation<E> for Builtin {
+    fn apply(&mut self, x: E) -> E {
+        self.eval(x)
+    }
+
+    /// Exponentials and tanh take a few cycles, hard-swish is a compare and a multiply
+    fn latency(&self) -> u64 {
+        match self {
+            Builtin::Gelu => 6,
+            Builtin::Silu => 4,
+            Builtin::Tanh => 4,
+            Builtin::Sigmoid => 3,
+            Builtin::HardSwish => 2,
+        }
+    }
+}
 
 #[context_macro]
 pub struct Actfn<T: Clone> {
     input: Receiver<T>,
     output: Sender<T>,
     initiation_interval: u64,
-    func: fn(T) -> T,
+    func: Box<dyn Activation<T>>,
 }
 
 impl<T: DAMType> Actfn<T> {
@@ -14,6 +81,16 @@ impl<T: DAMType> Actfn<T> {
         output: Sender<T>,
         initiation_interval: u64,
         func: fn(T) -> T,
+    ) -> Self {
+        Self::from_activation(input, output, initiation_interval, Box::new(func))
+    }
+
+    /// Actfn applying a closure or any other `Activation`
+    pub fn from_activation(
+        input: Receiver<T>,
+        output: Sender<T>,
+        initiation_interval: u64,
+        func: Box<dyn Activation<T>>,
     ) -> Self {
         let result = Self {
             input,
@@ -30,18 +107,78 @@ impl<T: DAMType> Actfn<T> {
 
 impl<T: DAMType> Context for Actfn<T> {
     fn run(&mut self) {
+        let latency = self.func.latency();
         loop {
             match self.input.dequeue(&self.time) {
-                Ok(data) => self
-                    .output
-                    .enqueue(
-                        &self.time,
-                        ChannelElement::new(data.time + 1, (self.func)(data.data)),
-                    )
-                    .unwrap(),
+                Ok(data) => {
+                    let out = self.func.apply(data.data);
+                    self.output
+                        .enqueue(&self.time, ChannelElement::new(data.time + latency, out))
+                        .unwrap()
+                }
                 Err(_) => return,
             }
             self.time.incr_cycles(self.initiation_interval)
         }
     }
 }
+
+/// Actfn over `Array1` packets with `lanes` elements evaluated per initiation interval.
+/// A packet occupies the unit for `ceil(len / lanes) * initiation_interval` cycles and
+/// leaves the activation's latency after its last lane group, so the unit can sit at a
+/// mesh boundary port with the throughput of its width.
+#[context_macro]
+pub struct PacketActfn<E: Clone> {
+    input: Receiver<Array1<E>>,
+    output: Sender<Array1<E>>,
+    lanes: usize,
+    initiation_interval: u64,
+    func: Box<dyn Activation<E>>,
+}
+
+impl<E: DAMType> PacketActfn<E> {
+    pub fn new(
+        input: Receiver<Array1<E>>,
+        output: Sender<Array1<E>>,
+        lanes: usize,
+        initiation_interval: u64,
+        func: Box<dyn Activation<E>>,
+    ) -> Self {
+        assert!(lanes > 0);
+        let result = Self {
+            input,
+            output,
+            lanes,
+            initiation_interval,
+            func,
+            context_info: Default::default(),
+        };
+        result.input.attach_receiver(&result);
+        result.output.attach_sender(&result);
+        result
+    }
+
+    /// Cycles a packet of `len` elements occupies the unit
+    pub fn packet_cycles(&self, len: usize) -> u64 {
+        len.div_ceil(self.lanes) as u64 * self.initiation_interval
+    }
+}
+
+impl<E: DAMType> Context for PacketActfn<E> {
+    fn run(&mut self) {
+        let latency = self.func.latency();
+        loop {
+            match self.input.dequeue(&self.time) {
+                Ok(data) => {
+                    let out = data.data.mapv(|x| self.func.apply(x));
+                    self.time.incr_cycles(self.packet_cycles(out.len()));
+                    let ready = self.time.tick() + latency.saturating_sub(1);
+                    self.output
+                        .enqueue(&self.time, ChannelElement::new(ready, out))
+                        .unwrap()
+                }
+                Err(_) => return,
+            }
+        }
+    }
+}
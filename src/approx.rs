@@ -0,0 +1,127 @@
+use std::sync::{Arc, Mutex};
+
+use ndarray::NdFloat;
+
+use crate::actfn::Activation;
+
+/// Table approximating a function over `[lo, hi]`, inputs outside are clamped
+#[derive(Clone, Debug, PartialEq)]
+pub enum ApproxTable {
+    /// One value per equal width bucket, taken at the bucket's center
+    Lut { lo: f64, hi: f64, values: Vec<f64> },
+    /// Linear interpolation between equally spaced breakpoints
+    Pwl { lo: f64, hi: f64, points: Vec<f64> },
+}
+
+impl ApproxTable {
+    pub fn lut(func: fn(f64) -> f64, lo: f64, hi: f64, entries: usize) -> Self {
+        assert!(lo < hi && entries > 0);
+        let width = (hi - lo) / entries as f64;
+        let values = Vec::from_iter((0..entries).map(|i| func(lo + (i as f64 + 0.5) * width)));
+        ApproxTable::Lut { lo, hi, values }
+    }
+
+    pub fn pwl(func: fn(f64) -> f64, lo: f64, hi: f64, segments: usize) -> Self {
+        assert!(lo < hi && segments > 0);
+        let width = (hi - lo) / segments as f64;
+        let points = Vec::from_iter((0..=segments).map(|i| func(lo + i as f64 * width)));
+        ApproxTable::Pwl { lo, hi, points }
+    }
+
+    pub fn eval(&self, x: f64) -> f64 {
+        match self {
+            ApproxTable::Lut { lo, hi, values } => {
+                let pos = (x.clamp(*lo, *hi) - lo) / (hi - lo) * values.len() as f64;
+                values[(pos as usize).min(values.len() - 1)]
+            }
+            ApproxTable::Pwl { lo, hi, points } => {
+                let segments = points.len() - 1;
+                let pos = (x.clamp(*lo, *hi) - lo) / (hi - lo) * segments as f64;
+                let seg = (pos as usize).min(segments - 1);
+                let frac = pos - seg as f64;
+                points[seg] + frac * (points[seg + 1] - points[seg])
+            }
+        }
+    }
+}
+
+/// Error of an approximation over the values it processed
+/// bin_width - Width of a histogram bin of absolute errors, the last bin holds the rest
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ApproxReport {
+    pub count: usize,
+    pub max_abs_error: f64,
+    pub sum_error: f64,
+    pub sum_abs_error: f64,
+    pub bin_width: f64,
+    pub histogram: Vec<usize>,
+}
+
+impl ApproxReport {
+    pub fn new(bins: usize, bin_width: f64) -> Self {
+        assert!(bins > 0 && bin_width > 0.0);
+        Self {
+            bin_width,
+            histogram: vec![0; bins],
+            ..Default::default()
+        }
+    }
+
+    pub fn record(&mut self, approx: f64, exact: f64) {
+        let error = approx - exact;
+        self.count += 1;
+        self.max_abs_error = self.max_abs_error.max(error.abs());
+        self.sum_error += error;
+        self.sum_abs_error += error.abs();
+        let bin = (error.abs() / self.bin_width) as usize;
+        let last = self.histogram.len() - 1;
+        self.histogram[bin.min(last)] += 1;
+    }
+
+    /// Signed mean, shows a bias of the table
+    pub fn mean_error(&self) -> f64 {
+        self.sum_error / self.count.max(1) as f64
+    }
+
+    pub fn mean_abs_error(&self) -> f64 {
+        self.sum_abs_error / self.count.max(1) as f64
+    }
+}
+
+/// Activation evaluated from an `ApproxTable`, each result is compared against `exact`
+/// and recorded in `report`. A lookup takes one cycle, an interpolation two.
+pub struct TableActivation {
+    table: ApproxTable,
+    exact: fn(f64) -> f64,
+    report: Arc<Mutex<ApproxReport>>,
+}
+
+impl TableActivation {
+    pub fn new(
+        table: ApproxTable,
+        exact: fn(f64) -> f64,
+        report: Arc<Mutex<ApproxReport>>,
+    ) -> Self {
+        Self {
+            table,
+            exact,
+            report,
+        }
+    }
+}
+
+impl<E: NdFloat> Activation<E> for TableActivation {
+    fn apply(&mut self, x: E) -> E {
+        let x = x.to_f64().unwrap();
+        let approx = self.table.eval(x);
+        self.report.lock().unwrap().record(approx, (self.exact)(x));
+        E::from(approx).unwrap()
+    }
+
+    fn latency(&self) -> u64 {
+        match self.table {
+            ApproxTable::Lut { .. } => 1,
+            ApproxTable::Pwl { .. } => 2,
+        }
+    }
+}
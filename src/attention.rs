@@ -0,0 +1,322 @@
+use std::collections::VecDeque;
+
+use dam::{
+    context_tools::*,
+    simulation::{InitializationOptionsBuilder, ProgramBuilder, RunOptions},
+};
+use ndarray::{LinalgScalar, NdFloat, concatenate, prelude::*};
+use strum::EnumCount;
+
+use crate::{
+    gemm::{Dataflow, Tracks},
+    mapper::Mapping,
+    mesh::{Hop, MeshHarness, MeshSink, take_left_inputs},
+    softmax::{Softmax, SoftmaxConstants, SoftmaxMode, softmax},
+    trace,
+};
+
+/// Cycles of every head. A head runs QKᵀ, the softmax and the product with V as one
+/// program, so the stages overlap.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AttentionReport {
+    pub head_cycles: Vec<u64>,
+}
+
+impl AttentionReport {
+    pub fn total(&self) -> u64 {
+        self.head_cycles.iter().sum()
+    }
+}
+
+/// Joins the column blocks leaving the bottom of a mesh into rows of `width` elements,
+/// dropping the padding columns and every row after the first `num_rows`. Input `c`
+/// carries packets of `tile` wide rows of block `c`. Sends a row per cycle.
+#[context_macro]
+pub struct RowGather<E: Clone> {
+    inputs: Vec<Receiver<Array1<E>>>,
+    output: Sender<Array1<E>>,
+    tile: usize,
+    width: usize,
+    num_rows: usize,
+}
+
+impl<E: DAMType> RowGather<E> {
+    pub fn new(
+        inputs: Vec<Receiver<Array1<E>>>,
+        output: Sender<Array1<E>>,
+        tile: usize,
+        width: usize,
+        num_rows: usize,
+    ) -> Self {
+        assert!(width <= inputs.len() * tile);
+        let result = Self {
+            inputs,
+            output,
+            tile,
+            width,
+            num_rows,
+            context_info: Default::default(),
+        };
+        result
+            .inputs
+            .iter()
+            .for_each(|x| x.attach_receiver(&result));
+        result.output.attach_sender(&result);
+        result
+    }
+}
+
+impl<E: DAMType> Context for RowGather<E> {
+    fn run(&mut self) {
+        let mut pending = vec![VecDeque::new(); self.inputs.len()];
+        for row_idx in 0.. {
+            let mut row = Vec::with_capacity(self.inputs.len() * self.tile);
+            for (input, block) in self.inputs.iter().zip(pending.iter_mut()) {
+                while block.len() < self.tile {
+                    match input.dequeue(&self.time) {
+                        Ok(data) => block.extend(data.data),
+                        Err(_) if row.is_empty() => return,
+                        Err(_) => panic!("Column closed in the middle of a row"),
+                    }
+                }
+                row.extend(block.drain(..self.tile));
+            }
+            if row_idx >= self.num_rows {
+                continue;
+            }
+            row.truncate(self.width);
+            let ce = ChannelElement::new(self.time.tick() + 1, Array::from_vec(row));
+            self.output.enqueue(&self.time, ce).unwrap();
+            self.time.incr_cycles(1);
+        }
+    }
+}
+
+/// Inverse of `RowGather`, splits `num_rows` rows into `tile` wide blocks, zero padded to
+/// `outputs.len() * tile`, and streams block `r` to output `r` in `link_capacity`
+/// packets. Missing rows are zeros and extra rows are dropped. Sends a packet to every
+/// output per cycle.
+#[context_macro]
+pub struct RowScatter<E: Clone> {
+    input: Receiver<Array1<E>>,
+    outputs: Vec<Sender<Array1<E>>>,
+    tile: usize,
+    link_capacity: usize,
+    num_rows: usize,
+}
+
+impl<E: DAMType + LinalgScalar> RowScatter<E> {
+    pub fn new(
+        input: Receiver<Array1<E>>,
+        outputs: Vec<Sender<Array1<E>>>,
+        tile: usize,
+        link_capacity: usize,
+        num_rows: usize,
+    ) -> Self {
+        assert!(link_capacity.is_multiple_of(tile));
+        assert!((num_rows * tile).is_multiple_of(link_capacity));
+        let result = Self {
+            input,
+            outputs,
+            tile,
+            link_capacity,
+            num_rows,
+            context_info: Default::default(),
+        };
+        result.input.attach_receiver(&result);
+        result.outputs.iter().for_each(|x| x.attach_sender(&result));
+        result
+    }
+}
+
+impl<E: DAMType + LinalgScalar> Context for RowScatter<E> {
+    fn run(&mut self) {
+        let width = self.outputs.len() * self.tile;
+        let mut packets = vec![Vec::with_capacity(self.link_capacity); self.outputs.len()];
+        for _ in 0..self.num_rows {
+            let mut row = match self.input.dequeue(&self.time) {
+                Ok(data) => data.data.to_vec(),
+                Err(_) => Vec::new(),
+            };
+            assert!(row.len() <= width);
+            row.resize(width, E::zero());
+            for (packet, block) in packets.iter_mut().zip(row.chunks(self.tile)) {
+                packet.extend_from_slice(block);
+            }
+            if packets[0].len() == self.link_capacity {
+                let cur_time = self.time.tick();
+                for (output, packet) in self.outputs.iter().zip(packets.iter_mut()) {
+                    let data = Array::from_vec(std::mem::take(packet));
+                    output
+                        .enqueue(&self.time, ChannelElement::new(cur_time + 1, data))
+                        .unwrap();
+                }
+                self.time.incr_cycles(1);
+            }
+        }
+        while self.input.dequeue(&self.time).is_ok() {}
+    }
+}
+
+/// Multi-head scaled dot-product attention, `softmax(Q Kᵀ / sqrt(head_dim)) V` per head.
+/// `Q`, `K` and `V` are `[seq_len, num_heads * head_dim]` with head `h` in columns
+/// `h * head_dim..(h + 1) * head_dim`. QKᵀ runs on one mesh of `dims`, its rows go
+/// through a `Softmax` context straight into a second mesh multiplying them with V.
+/// Both products of a head have to fit their mesh in a single pass. The heads run one
+/// after the other.
+pub struct Attention {
+    num_heads: usize,
+    head_dim: usize,
+    dims: [usize; 2],
+    link_capacity: usize,
+    buffer_size: usize,
+    softmax: SoftmaxConstants,
+}
+
+impl Attention {
+    pub fn new(
+        num_heads: usize,
+        head_dim: usize,
+        dims: [usize; 2],
+        link_capacity: usize,
+        buffer_size: usize,
+    ) -> Self {
+        assert!(num_heads > 0 && head_dim > 0);
+        Self {
+            num_heads,
+            head_dim,
+            dims,
+            link_capacity,
+            buffer_size,
+            softmax: SoftmaxConstants::new(SoftmaxMode::TwoPass, 1, 1),
+        }
+    }
+
+    pub fn with_softmax(mut self, softmax: SoftmaxConstants) -> Self {
+        self.softmax = softmax;
+        self
+    }
+
+    fn head<E: NdFloat>(&self, x: &Array2<E>, head: usize) -> Array2<E> {
+        assert!(x.ncols() == self.num_heads * self.head_dim);
+        let cols = head * self.head_dim..(head + 1) * self.head_dim;
+        x.slice(s![.., cols]).to_owned()
+    }
+
+    fn scale<E: NdFloat>(&self) -> E {
+        E::from(self.head_dim).unwrap().sqrt().recip()
+    }
+
+    pub fn reference<E: NdFloat>(&self, q: &Array2<E>, k: &Array2<E>, v: &Array2<E>) -> Array2<E> {
+        let heads = Vec::from_iter((0..self.num_heads).map(|h| {
+            let mut probs = self.head(q, h).dot(&self.head(k, h).t()) * self.scale::<E>();
+            for mut row in probs.rows_mut() {
+                let out = softmax(row.view());
+                row.assign(&out);
+            }
+            probs.dot(&self.head(v, h))
+        }));
+        let views = Vec::from_iter(heads.iter().map(|x| x.view()));
+        concatenate(Axis(1), &views).unwrap()
+    }
+
+    /// Runs head `h` as one program, returns its output, `[seq_len, head_dim]`, and cycles
+    fn run_head<E>(
+        &self,
+        q: &Array2<E>,
+        k: &Array2<E>,
+        v: &Array2<E>,
+        h: usize,
+        hop: Hop,
+        dataflow: Dataflow,
+    ) -> (Array2<E>, u64)
+    where
+        E: DAMType + NdFloat + std::fmt::Debug,
+    {
+        let (dims, link_cap, buffer_size) = (self.dims, self.link_capacity, self.buffer_size);
+        let seq_len = q.nrows();
+        // The scale is folded into the stationary operand
+        let kt = self.head(k, h).t().to_owned() * self.scale::<E>();
+        let qk = Mapping::new(self.head(q, h), kt, dims, link_cap, buffer_size);
+        let pv = Mapping::streamed(seq_len, self.head(v, h), dims, link_cap, buffer_size);
+        assert!(
+            qk.num_passes() == 1 && pv.num_passes() == 1,
+            "A head has to fit the mesh in one pass"
+        );
+
+        let num_nodes: usize = 2 * dims.iter().product::<usize>();
+        let thread_names = Vec::from_iter((0..num_nodes).map(|n| format!("xpu{n}")));
+        let processes = vec![("xpu".to_string(), thread_names)];
+        let track_ids =
+            trace::get_trace_descriptors::<{ Tracks::COUNT }>(processes, num_nodes + 1, num_nodes);
+        let mut ctx = ProgramBuilder::default();
+        let (qk_prods, qk_cons) = qk.add_nodes(0, 0, hop, dataflow, &track_ids, 0, &mut ctx);
+        let (mut pv_prods, pv_cons) =
+            pv.add_nodes(0, 0, hop, dataflow, &track_ids, num_nodes / 2, &mut ctx);
+
+        // QKᵀ columns -> score rows -> softmax -> PV rows
+        let (col_sends, col_recvs): (Vec<_>, Vec<_>) =
+            (0..dims[1]).map(|_| ctx.bounded(buffer_size)).unzip();
+        let (score_send, score_recv) = ctx.bounded(buffer_size);
+        let (prob_send, prob_recv) = ctx.bounded(buffer_size);
+        let qk_tiling = qk.tiling();
+        ctx.add_child(RowGather::new(
+            col_recvs,
+            score_send,
+            qk_tiling.tn,
+            seq_len,
+            seq_len,
+        ));
+        ctx.add_child(Softmax::new(score_recv, prob_send, self.softmax));
+        let pv_tiling = pv.tiling();
+        ctx.add_child(RowScatter::new(
+            prob_recv,
+            take_left_inputs(&mut pv_prods),
+            pv_tiling.tk,
+            link_cap,
+            pv.num_inputs(),
+        ));
+        qk.harness(0)
+            .attach(qk_prods, qk_cons, MeshSink::Forward(col_sends), &mut ctx);
+        let pv_harness =
+            MeshHarness::chained(dims, link_cap, pv.num_inputs(), pv_tiling.tk, pv_tiling.tn);
+        let output = pv_harness.attach(pv_prods, pv_cons, MeshSink::Collect, &mut ctx);
+        let executed = ctx
+            .initialize(
+                InitializationOptionsBuilder::default()
+                    .run_flavor_inference(true)
+                    .build()
+                    .unwrap(),
+            )
+            .unwrap()
+            .run(RunOptions::default());
+        let y = output.to_array();
+        (
+            y.slice(s![..seq_len, ..self.head_dim]).to_owned(),
+            executed.elapsed_cycles().unwrap_or(0),
+        )
+    }
+
+    /// Runs every head and returns the concatenated outputs, `[seq_len, num_heads * head_dim]`
+    pub fn run<E>(
+        &self,
+        q: &Array2<E>,
+        k: &Array2<E>,
+        v: &Array2<E>,
+        hop: Hop,
+        dataflow: Dataflow,
+    ) -> (Array2<E>, AttentionReport)
+    where
+        E: DAMType + NdFloat + std::fmt::Debug,
+    {
+        assert!(q.nrows() == k.nrows() && k.nrows() == v.nrows());
+        let mut report = AttentionReport::default();
+        let heads = Vec::from_iter((0..self.num_heads).map(|h| {
+            let (out, cycles) = self.run_head(q, k, v, h, hop, dataflow);
+            report.head_cycles.push(cycles);
+            out
+        }));
+        let views = Vec::from_iter(heads.iter().map(|x| x.view()));
+        (concatenate(Axis(1), &views).unwrap(), report)
+    }
+}
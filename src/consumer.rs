@@ -1,3 +1,5 @@
+use std::sync::{Arc, Mutex};
+
 use dam::context_tools::*;
 
 #[context_macro]
@@ -46,3 +48,36 @@ where
         }
     }
 }
+
+/// Stores everything it receives so the data can be read back after the simulation
+#[context_macro]
+pub struct Collector<T: DAMType> {
+    input: Receiver<T>,
+    buffer: Arc<Mutex<Vec<T>>>,
+}
+
+impl<T: DAMType> Collector<T> {
+    pub fn new(input: Receiver<T>, buffer: Arc<Mutex<Vec<T>>>) -> Self {
+        let result = Self {
+            input,
+            buffer,
+            context_info: Default::default(),
+        };
+        result.input.attach_receiver(&result);
+        result
+    }
+}
+
+impl<T> Context for Collector<T>
+where
+    T: DAMType,
+{
+    fn run(&mut self) {
+        loop {
+            match self.input.dequeue(&self.time) {
+                Ok(x) => self.buffer.lock().unwrap().push(x.data),
+                Err(_) => return,
+            }
+        }
+    }
+}
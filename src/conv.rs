@@ -0,0 +1,357 @@
+use std::sync::{Arc, Mutex};
+
+use dam::context_tools::*;
+use ndarray::{LinalgScalar, prelude::*};
+
+use crate::{epilogue::EpilogueParams, gemm::Dataflow, mapper::Mapping, mesh::Hop};
+
+/// Constants for a 2D convolution, every pair is (height, width)
+/// kernel - Kernel size
+/// stride - Step between neighbouring output pixels
+/// padding - Zeros added on both sides of the image
+/// dilation - Step between neighbouring kernel taps
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Conv2dParams {
+    pub kernel: [usize; 2],
+    pub stride: [usize; 2],
+    pub padding: [usize; 2],
+    pub dilation: [usize; 2],
+}
+
+impl Conv2dParams {
+    pub fn new(kernel: [usize; 2]) -> Self {
+        assert!(kernel.iter().all(|k| *k > 0));
+        Self {
+            kernel,
+            stride: [1, 1],
+            padding: [0, 0],
+            dilation: [1, 1],
+        }
+    }
+
+    pub fn with_stride(mut self, stride: [usize; 2]) -> Self {
+        assert!(stride.iter().all(|s| *s > 0));
+        self.stride = stride;
+        self
+    }
+
+    pub fn with_padding(mut self, padding: [usize; 2]) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn with_dilation(mut self, dilation: [usize; 2]) -> Self {
+        assert!(dilation.iter().all(|d| *d > 0));
+        self.dilation = dilation;
+        self
+    }
+
+    /// Output (height, width) of an image of `size`
+    pub fn output_size(&self, size: [usize; 2]) -> [usize; 2] {
+        [0, 1].map(|i| {
+            let span = self.dilation[i] * (self.kernel[i] - 1) + 1;
+            let padded = size[i] + 2 * self.padding[i];
+            assert!(padded >= span, "Kernel larger than the padded image");
+            (padded - span) / self.stride[i] + 1
+        })
+    }
+
+    /// Shape `[M, K]` of the im2col matrix of an image `[B, C, H, W]`
+    pub fn im2col_shape(&self, image: &[usize]) -> [usize; 2] {
+        let [oh, ow] = self.output_size([image[2], image[3]]);
+        let [kh, kw] = self.kernel;
+        [image[0] * oh * ow, image[1] * kh * kw]
+    }
+
+    /// Image element at row `m`, output pixel `(b, oh, ow)`, and column `k`, kernel tap
+    /// `(c, kh, kw)`, of the im2col matrix. `None` in the padding.
+    pub fn source(&self, image: &[usize], m: usize, k: usize) -> Option<[usize; 4]> {
+        let [oh, ow] = self.output_size([image[2], image[3]]);
+        let [kh, kw] = self.kernel;
+        let (b, pixel) = (m / (oh * ow), m % (oh * ow));
+        let (c, tap) = (k / (kh * kw), k % (kh * kw));
+        let pos = [(pixel / ow, tap / kw), (pixel % ow, tap % kw)];
+        let [y, x] = [0, 1].map(|i| {
+            let (out, tap) = pos[i];
+            (out * self.stride[i] + tap * self.dilation[i]).checked_sub(self.padding[i])
+        });
+        match (y, x) {
+            (Some(y), Some(x)) if y < image[2] && x < image[3] => Some([b, c, y, x]),
+            _ => None,
+        }
+    }
+
+    /// Element `(m, k)` of the im2col matrix, zero outside the matrix and in the padding
+    fn im2col_value<E: LinalgScalar>(&self, image: &Array4<E>, m: usize, k: usize) -> E {
+        let [rows, cols] = self.im2col_shape(image.shape());
+        if m >= rows || k >= cols {
+            return E::zero();
+        }
+        match self.source(image.shape(), m, k) {
+            Some(idx) => image[idx],
+            None => E::zero(),
+        }
+    }
+
+    /// Lowers `image`, `[B, C, H, W]`, to the `[M, K]` matrix multiplied by the weights
+    pub fn im2col<E: LinalgScalar>(&self, image: &Array4<E>) -> Array2<E> {
+        Array2::from_shape_fn(self.im2col_shape(image.shape()), |(m, k)| {
+            self.im2col_value(image, m, k)
+        })
+    }
+}
+
+/// Where the im2col matrix comes from
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Im2colMode {
+    /// Timing of a matrix lowered ahead of time: a packet per cycle and one read per
+    /// element. The values are still gathered from the image, only the cost differs.
+    Precomputed,
+    /// Gathered from the image, one image read per cycle
+    OnTheFly,
+}
+
+/// Activation traffic of a convolution in elements, `sent` and `input` are counted by
+/// the `Im2col` feeders over every pass
+/// image - Size of the image
+/// im2col - Size of the lowered matrix, the image with every overlapping tap repeated
+/// sent - Fed into the left boundary of the mesh, tiling padding included
+/// input - Read from memory: the lowered matrix when precomputed, the image otherwise
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ConvTraffic {
+    pub image: usize,
+    pub im2col: usize,
+    pub sent: usize,
+    pub input: usize,
+}
+
+impl ConvTraffic {
+    /// How many times an image element is repeated by im2col
+    pub fn duplication(&self) -> f64 {
+        self.im2col as f64 / self.image.max(1) as f64
+    }
+}
+
+/// Streams columns `cols` of the im2col matrix of `image`, all `num_rows` rows, as
+/// `link_capacity` packets in row major order. Rows and columns past the matrix are zero.
+/// Feeds a mesh row in place of a `Producer` of the lowered matrix. A precomputed
+/// packet takes a cycle, a gathered one takes a cycle per tap that is not padding.
+#[context_macro]
+pub struct Im2col<E: Clone> {
+    image: Arc<Array4<E>>,
+    params: Conv2dParams,
+    mode: Im2colMode,
+    cols: std::ops::Range<usize>,
+    num_rows: usize,
+    link_capacity: usize,
+    output: Sender<Array1<E>>,
+    stats: Option<Arc<Mutex<ConvTraffic>>>,
+}
+
+impl<E: DAMType + LinalgScalar> Im2col<E> {
+    pub fn new(
+        image: Arc<Array4<E>>,
+        params: Conv2dParams,
+        mode: Im2colMode,
+        cols: std::ops::Range<usize>,
+        num_rows: usize,
+        link_capacity: usize,
+        output: Sender<Array1<E>>,
+    ) -> Self {
+        assert!((num_rows * cols.len()).is_multiple_of(link_capacity));
+        let result = Self {
+            image,
+            params,
+            mode,
+            cols,
+            num_rows,
+            link_capacity,
+            output,
+            stats: None,
+            context_info: Default::default(),
+        };
+        result.output.attach_sender(&result);
+        result
+    }
+
+    /// Adds the sent and read elements to `stats` when it ends
+    pub fn with_stats(mut self, stats: Arc<Mutex<ConvTraffic>>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Memory reads of element `(m, k)`
+    fn reads(&self, m: usize, k: usize) -> usize {
+        let shape = self.image.shape();
+        let [rows, cols] = self.params.im2col_shape(shape);
+        match self.mode {
+            Im2colMode::Precomputed => usize::from(m < rows && k < cols),
+            Im2colMode::OnTheFly if m < rows && k < cols => {
+                usize::from(self.params.source(shape, m, k).is_some())
+            }
+            Im2colMode::OnTheFly => 0,
+        }
+    }
+}
+
+impl<E: DAMType + LinalgScalar> Context for Im2col<E> {
+    fn run(&mut self) {
+        let mut counts = ConvTraffic::default();
+        let mut packet = Vec::with_capacity(self.link_capacity);
+        let mut packet_reads = 0;
+        for m in 0..self.num_rows {
+            for k in self.cols.clone() {
+                packet.push(self.params.im2col_value(&self.image, m, k));
+                packet_reads += self.reads(m, k);
+                if packet.len() == self.link_capacity {
+                    let cycles = match self.mode {
+                        Im2colMode::Precomputed => 1,
+                        Im2colMode::OnTheFly => packet_reads.max(1) as u64,
+                    };
+                    self.time.incr_cycles(cycles - 1);
+                    let data = Array1::from_vec(std::mem::take(&mut packet));
+                    let ce = ChannelElement::new(self.time.tick() + 1, data);
+                    self.output.enqueue(&self.time, ce).unwrap();
+                    self.time.incr_cycles(1);
+                    counts.sent += self.link_capacity;
+                    counts.input += packet_reads;
+                    packet_reads = 0;
+                }
+            }
+        }
+        if let Some(stats) = self.stats.as_ref() {
+            let mut stats = stats.lock().unwrap();
+            stats.sent += counts.sent;
+            stats.input += counts.input;
+        }
+    }
+}
+
+/// Conv2d lowered to `Y = im2col(X) W` on the mesh, with
+/// `W: [C * KH * KW, O]` the flattened weights `[O, C, KH, KW]`
+pub struct Conv2d<E> {
+    weights: Array4<E>,
+    params: Conv2dParams,
+    epilogue: Option<EpilogueParams<E>>,
+    dims: [usize; 2],
+    link_capacity: usize,
+    buffer_size: usize,
+}
+
+impl<E> Conv2d<E>
+where
+    E: DAMType + LinalgScalar + PartialEq + std::fmt::Debug,
+{
+    /// weights - `[O, C, KH, KW]`
+    pub fn new(
+        weights: Array4<E>,
+        params: Conv2dParams,
+        dims: [usize; 2],
+        link_capacity: usize,
+        buffer_size: usize,
+    ) -> Self {
+        assert!(weights.shape()[2..] == params.kernel);
+        Self {
+            weights,
+            params,
+            epilogue: None,
+            dims,
+            link_capacity,
+            buffer_size,
+        }
+    }
+
+    /// Adds `bias`, `[O]`, and applies `func` to every output
+    pub fn with_epilogue(mut self, bias: Array1<E>, func: fn(E) -> E) -> Self {
+        assert!(bias.len() == self.weights.shape()[0]);
+        self.epilogue = Some((bias, func));
+        self
+    }
+
+    /// Weights as the `[C * KH * KW, O]` matrix matching the im2col columns
+    pub fn weight_matrix(&self) -> Array2<E> {
+        let out_channels = self.weights.shape()[0];
+        let flat = Array::from_iter(self.weights.iter().cloned());
+        let w = flat.into_shape([out_channels, self.weights.len() / out_channels]);
+        w.unwrap().t().to_owned()
+    }
+
+    /// Direct convolution of `image`, `[B, C, H, W]`, returns `[B, O, OH, OW]`
+    pub fn reference(&self, image: &Array4<E>) -> Array4<E> {
+        let &[batch, channels, ..] = image.shape() else {
+            unreachable!()
+        };
+        let &[out_channels, in_channels, kh, kw] = self.weights.shape() else {
+            unreachable!()
+        };
+        assert!(channels == in_channels);
+        let [oh, ow] = self
+            .params
+            .output_size([image.shape()[2], image.shape()[3]]);
+        let mut y = Array4::zeros([batch, out_channels, oh, ow]);
+        for ((b, o, i, j), out) in y.indexed_iter_mut() {
+            let m = (b * oh + i) * ow + j;
+            let mut acc = E::zero();
+            for c in 0..channels {
+                for (ki, kj) in (0..kh).flat_map(|ki| (0..kw).map(move |kj| (ki, kj))) {
+                    let k = (c * kh + ki) * kw + kj;
+                    if let Some(idx) = self.params.source(image.shape(), m, k) {
+                        acc = acc + image[idx] * self.weights[[o, c, ki, kj]];
+                    }
+                }
+            }
+            *out = match self.epilogue.as_ref() {
+                Some((bias, func)) => func(acc + bias[o]),
+                None => acc,
+            };
+        }
+        y
+    }
+
+    /// Runs the convolution of `image`, `[B, C, H, W]`, on the mesh and returns
+    /// `[B, O, OH, OW]`, the cycles and the activation traffic
+    pub fn run(
+        &self,
+        image: &Array4<E>,
+        mode: Im2colMode,
+        hop: Hop,
+        dataflow: Dataflow,
+    ) -> (Array4<E>, u64, ConvTraffic) {
+        let [m, k] = self.params.im2col_shape(image.shape());
+        let (dims, link_cap, buffer_size) = (self.dims, self.link_capacity, self.buffer_size);
+        let with_epilogue = |mapping: Mapping<E>| match self.epilogue.as_ref() {
+            Some((bias, func)) => mapping.with_epilogue(bias.clone(), *func),
+            None => mapping,
+        };
+        let stats = Arc::new(Mutex::new(ConvTraffic {
+            image: image.len(),
+            im2col: m * k,
+            ..Default::default()
+        }));
+        let w = self.weight_matrix();
+        let mapping = with_epilogue(Mapping::streamed(m, w, dims, link_cap, buffer_size));
+        let (params, num_rows) = (self.params, mapping.num_inputs());
+        let (feed_image, feed_stats) = (Arc::new(image.clone()), stats.clone());
+        // Every pass, re-streamed ones included, goes through the counting feeders
+        let (y, cycles) = mapping.run_streamed(hop, dataflow, &move |cols, x_send, ctx| {
+            let feeder = Im2col::new(
+                feed_image.clone(),
+                params,
+                mode,
+                cols,
+                num_rows,
+                link_cap,
+                x_send,
+            );
+            ctx.add_child(feeder.with_stats(feed_stats.clone()));
+        });
+        let traffic = *stats.lock().unwrap();
+        // Rows of Y are (b, oh, ow) pixels, columns are output channels
+        let [oh, ow] = self
+            .params
+            .output_size([image.shape()[2], image.shape()[3]]);
+        let y = y.into_shape([image.shape()[0], oh, ow, self.weights.shape()[0]]);
+        let y = y.unwrap().permuted_axes([0, 3, 1, 2]);
+        (y.as_standard_layout().to_owned(), cycles, traffic)
+    }
+}
@@ -0,0 +1,243 @@
+use std::sync::{Arc, Mutex};
+
+use dam::{context_tools::*, simulation::ProgramBuilder};
+use strum::EnumCount;
+
+use crate::trace::{self, perfetto::TracePacket};
+
+#[derive(
+    strum_macros::EnumCount,
+    strum_macros::Display,
+    strum_macros::EnumString,
+    strum_macros::VariantArray,
+    Copy,
+    Clone,
+    Debug,
+)]
+pub enum CreditTracks {
+    Send = 0,
+    Starved = 1,
+}
+
+/// Credit based flow control parameters
+/// credits - Slots in the receiver FIFO. The sender stalls once all of them are in use.
+/// latency - Cycles for a packet to reach the receiver
+/// credit_latency - Cycles for a returned credit to reach the sender
+#[derive(Copy, Clone, Debug)]
+pub struct CreditConfig {
+    credits: usize,
+    latency: u64,
+    credit_latency: u64,
+}
+
+impl CreditConfig {
+    pub fn new(credits: usize, latency: u64, credit_latency: u64) -> Self {
+        assert!(credits > 0);
+        Self {
+            credits,
+            latency,
+            credit_latency,
+        }
+    }
+
+    pub fn credits(&self) -> usize {
+        self.credits
+    }
+
+    /// Cycles from sending a packet until its credit is usable again
+    pub fn round_trip(&self) -> u64 {
+        self.latency + self.credit_latency + 1
+    }
+
+    /// Packets per cycle the link sustains. Below 1.0 the link is credit bound.
+    pub fn max_throughput(&self) -> f64 {
+        (self.credits as f64 / self.round_trip() as f64).min(1.0)
+    }
+}
+
+/// Traffic of the sending side of a credit link
+/// packets - Packets sent
+/// starved_cycles - Cycles spent waiting on a returned credit with a packet ready
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CreditStats {
+    pub packets: usize,
+    pub starved_cycles: u64,
+}
+
+/// Sending side of a credit flow controlled link.
+/// Holds `credits` and spends one per packet. Waits on returned credits when it runs out.
+#[context_macro]
+pub struct CreditSender<T: Clone> {
+    input: Receiver<T>,
+    output: Sender<T>,
+    credit_in: Receiver<usize>,
+    config: CreditConfig,
+    thread_id: u32,
+    track_ids: Option<[u64; CreditTracks::COUNT]>,
+    stats: Arc<Mutex<CreditStats>>,
+}
+
+impl<T: DAMType> CreditSender<T> {
+    pub fn new(
+        input: Receiver<T>,
+        output: Sender<T>,
+        credit_in: Receiver<usize>,
+        config: CreditConfig,
+        thread_id: u32,
+        track_ids: Option<[u64; CreditTracks::COUNT]>,
+        stats: Arc<Mutex<CreditStats>>,
+    ) -> Self {
+        let result = Self {
+            input,
+            output,
+            credit_in,
+            config,
+            thread_id,
+            track_ids,
+            stats,
+            context_info: Default::default(),
+        };
+        result.input.attach_receiver(&result);
+        result.output.attach_sender(&result);
+        result.credit_in.attach_receiver(&result);
+        result
+    }
+
+    fn evt_slice(&self, evt: CreditTracks, timestamps: [u64; 2]) -> Vec<TracePacket> {
+        match self.track_ids {
+            Some(track_ids) => trace::mk_time_slice(
+                self.thread_id,
+                track_ids[evt as usize],
+                evt.to_string().as_str(),
+                timestamps,
+            )
+            .to_vec(),
+            None => vec![],
+        }
+    }
+}
+
+impl<T: DAMType> Context for CreditSender<T> {
+    fn run(&mut self) {
+        let mut credits = self.config.credits;
+        let (mut packets, mut starved_cycles) = (0, 0);
+        let mut tpkts = Vec::<TracePacket>::new();
+        loop {
+            let data = match self.input.dequeue(&self.time) {
+                Ok(data) => data.data,
+                Err(_) => break,
+            };
+            if credits == 0 {
+                let start = self.time.tick().time();
+                credits += self
+                    .credit_in
+                    .dequeue(&self.time)
+                    .expect("Credit channel closed with packets in flight")
+                    .data;
+                let end = self.time.tick().time();
+                if end > start {
+                    starved_cycles += end - start;
+                    tpkts.extend(self.evt_slice(CreditTracks::Starved, [start, end]));
+                }
+            }
+            credits -= 1;
+            packets += 1;
+            let cur_time = self.time.tick();
+            tpkts
+                .extend(self.evt_slice(CreditTracks::Send, [cur_time.time(), cur_time.time() + 1]));
+            self.output
+                .enqueue(
+                    &self.time,
+                    ChannelElement::new(cur_time + self.config.latency + 1, data),
+                )
+                .unwrap();
+            self.time.incr_cycles(1);
+        }
+        if self.track_ids.is_some() {
+            trace::write_trace(
+                format!("credit_{tid}_.perfetto", tid = self.thread_id).as_str(),
+                tpkts,
+            );
+        }
+        let mut stats = self.stats.lock().unwrap();
+        stats.packets += packets;
+        stats.starved_cycles += starved_cycles;
+    }
+}
+
+/// Receiving side of a credit flow controlled link. Models the receiver FIFO,
+/// a credit goes back to the sender whenever a packet leaves the FIFO.
+#[context_macro]
+pub struct CreditReceiver<T: Clone> {
+    input: Receiver<T>,
+    output: Sender<T>,
+    credit_out: Sender<usize>,
+    config: CreditConfig,
+}
+
+impl<T: DAMType> CreditReceiver<T> {
+    pub fn new(
+        input: Receiver<T>,
+        output: Sender<T>,
+        credit_out: Sender<usize>,
+        config: CreditConfig,
+    ) -> Self {
+        let result = Self {
+            input,
+            output,
+            credit_out,
+            config,
+            context_info: Default::default(),
+        };
+        result.input.attach_receiver(&result);
+        result.output.attach_sender(&result);
+        result.credit_out.attach_sender(&result);
+        result
+    }
+}
+
+impl<T: DAMType> Context for CreditReceiver<T> {
+    fn run(&mut self) {
+        loop {
+            match self.input.dequeue(&self.time) {
+                Ok(data) => {
+                    self.output
+                        .enqueue(&self.time, ChannelElement::new(self.time.tick(), data.data))
+                        .unwrap();
+                    let credit_time = self.time.tick() + self.config.credit_latency;
+                    // The sender may finish before the last credits come back
+                    let _ = self
+                        .credit_out
+                        .enqueue(&self.time, ChannelElement::new(credit_time, 1));
+                }
+                Err(_) => return,
+            }
+            self.time.incr_cycles(1);
+        }
+    }
+}
+
+/// Connects `input` to `output` through a credit flow controlled link, the sender adds
+/// its traffic to `stats`
+pub fn credit_link<'a, T: DAMType>(
+    input: Receiver<T>,
+    output: Sender<T>,
+    config: CreditConfig,
+    thread_id: u32,
+    track_ids: Option<[u64; CreditTracks::COUNT]>,
+    stats: Arc<Mutex<CreditStats>>,
+    ctx: &mut ProgramBuilder<'a>,
+) {
+    let (data_send, data_recv) = ctx.bounded::<T>(config.credits);
+    let (credit_send, credit_recv) = ctx.bounded::<usize>(config.credits);
+    ctx.add_child(CreditSender::new(
+        input,
+        data_send,
+        credit_recv,
+        config,
+        thread_id,
+        track_ids,
+        stats,
+    ));
+    ctx.add_child(CreditReceiver::new(data_recv, output, credit_send, config));
+}
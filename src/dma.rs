@@ -0,0 +1,284 @@
+use dam::context_tools::*;
+use ndarray::prelude::*;
+use strum::EnumCount;
+
+use crate::{
+    memory::{MemRequest, MemResponse},
+    trace::{self, perfetto::TracePacket},
+};
+
+#[derive(
+    strum_macros::EnumCount,
+    strum_macros::Display,
+    strum_macros::EnumString,
+    strum_macros::VariantArray,
+    Copy,
+    Clone,
+    Debug,
+    PartialEq,
+)]
+pub enum DmaTracks {
+    Load = 0,
+    Store = 1,
+}
+
+/// Strided tensor transfer. Element `idx` of the tensor, in row major order over
+/// `shape`, lives at `base + sum(idx[i] * strides[i])` in memory.
+/// direction - `Load` moves memory to `outputs[port]`, `Store` moves `inputs[port]` to memory
+#[derive(Clone, Debug)]
+pub struct DmaDescriptor {
+    pub direction: DmaTracks,
+    pub base: usize,
+    pub shape: Vec<usize>,
+    pub strides: Vec<usize>,
+    pub port: usize,
+}
+
+impl DmaDescriptor {
+    pub fn load(base: usize, shape: Vec<usize>, strides: Vec<usize>, port: usize) -> Self {
+        assert!(shape.len() == strides.len());
+        Self {
+            direction: DmaTracks::Load,
+            base,
+            shape,
+            strides,
+            port,
+        }
+    }
+
+    pub fn store(base: usize, shape: Vec<usize>, strides: Vec<usize>, port: usize) -> Self {
+        let mut result = Self::load(base, shape, strides, port);
+        result.direction = DmaTracks::Store;
+        result
+    }
+
+    pub fn len(&self) -> usize {
+        self.shape.iter().product()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Memory address of every element in row major order
+    pub fn addresses(&self) -> Vec<usize> {
+        let mut addrs = Vec::with_capacity(self.len());
+        let mut idx = vec![0; self.shape.len()];
+        for _ in 0..self.len() {
+            addrs.push(
+                self.base
+                    + idx
+                        .iter()
+                        .zip(&self.strides)
+                        .map(|(i, s)| i * s)
+                        .sum::<usize>(),
+            );
+            for dim in (0..idx.len()).rev() {
+                idx[dim] += 1;
+                if idx[dim] < self.shape[dim] {
+                    break;
+                }
+                idx[dim] = 0;
+            }
+        }
+        addrs
+    }
+}
+
+/// Contiguous (addr, len) runs of `addrs`, one memory request each
+fn runs(addrs: &[usize]) -> Vec<(usize, usize)> {
+    let mut result: Vec<(usize, usize)> = Vec::new();
+    for &addr in addrs {
+        match result.last_mut() {
+            Some((start, len)) if *start + *len == addr => *len += 1,
+            _ => result.push((addr, 1)),
+        }
+    }
+    result
+}
+
+/// Constants for DMA
+/// link_capacity - Elements per packet sent to or received from a port
+/// outstanding - Memory requests in flight
+/// trace_prefix - Prepended to the trace file name, e.g. a subdirectory of the trace dir
+pub struct DmaConstants {
+    link_capacity: usize,
+    outstanding: usize,
+    thread_id: u32,
+    track_ids: Option<[u64; DmaTracks::COUNT]>,
+    trace_prefix: String,
+}
+
+impl DmaConstants {
+    pub fn new(
+        link_capacity: usize,
+        outstanding: usize,
+        thread_id: u32,
+        track_ids: Option<[u64; DmaTracks::COUNT]>,
+    ) -> Self {
+        assert!(link_capacity > 0 && outstanding > 0);
+        Self {
+            link_capacity,
+            outstanding,
+            thread_id,
+            track_ids,
+            trace_prefix: String::new(),
+        }
+    }
+
+    pub fn with_trace_prefix(mut self, trace_prefix: &str) -> Self {
+        self.trace_prefix = trace_prefix.to_string();
+        self
+    }
+}
+
+/// DMA engine moving strided tensors between a memory port and packet channels.
+/// Every packet is split into one request per contiguous run of addresses, so strided
+/// transfers cost more requests and bursts. Descriptors run one after the other.
+#[context_macro]
+pub struct Dma<E: Clone> {
+    descriptors: Vec<DmaDescriptor>,
+    constants: DmaConstants,
+    request: Sender<MemRequest<E>>,
+    response: Receiver<MemResponse<E>>,
+    outputs: Vec<Sender<Array1<E>>>,
+    inputs: Vec<Receiver<Array1<E>>>,
+}
+
+impl<E: DAMType> Dma<E> {
+    pub fn new(
+        descriptors: Vec<DmaDescriptor>,
+        constants: DmaConstants,
+        request: Sender<MemRequest<E>>,
+        response: Receiver<MemResponse<E>>,
+        outputs: Vec<Sender<Array1<E>>>,
+        inputs: Vec<Receiver<Array1<E>>>,
+    ) -> Self {
+        for desc in descriptors.iter() {
+            assert!(desc.len().is_multiple_of(constants.link_capacity));
+            match desc.direction {
+                DmaTracks::Load => assert!(desc.port < outputs.len()),
+                DmaTracks::Store => assert!(desc.port < inputs.len()),
+            }
+        }
+        let result = Self {
+            descriptors,
+            constants,
+            request,
+            response,
+            outputs,
+            inputs,
+            context_info: Default::default(),
+        };
+        result.request.attach_sender(&result);
+        result.response.attach_receiver(&result);
+        result.outputs.iter().for_each(|x| x.attach_sender(&result));
+        result
+            .inputs
+            .iter()
+            .for_each(|x| x.attach_receiver(&result));
+        result
+    }
+
+    fn evt_slice(&self, evt: DmaTracks, timestamps: [u64; 2]) -> Vec<TracePacket> {
+        match self.constants.track_ids {
+            Some(track_ids) => trace::mk_time_slice(
+                self.constants.thread_id,
+                track_ids[evt as usize],
+                evt.to_string().as_str(),
+                timestamps,
+            )
+            .to_vec(),
+            None => vec![],
+        }
+    }
+
+    fn issue(&self, req: MemRequest<E>) {
+        let ce = ChannelElement::new(self.time.tick() + 1, req);
+        self.request.enqueue(&self.time, ce).unwrap();
+        self.time.incr_cycles(1);
+    }
+
+    fn load(&self, desc: &DmaDescriptor) {
+        let addrs = desc.addresses();
+        // (addr, len, is the last run of its packet)
+        let mut reqs = Vec::new();
+        for packet in addrs.chunks(self.constants.link_capacity) {
+            let packet_runs = runs(packet);
+            let last = packet_runs.len() - 1;
+            reqs.extend(
+                packet_runs
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, (a, l))| (a, l, i == last)),
+            );
+        }
+        let mut issued = 0;
+        let mut packet = Vec::with_capacity(self.constants.link_capacity);
+        for received in 0..reqs.len() {
+            while issued < reqs.len() && issued - received < self.constants.outstanding {
+                let (addr, len, _) = reqs[issued];
+                self.issue(MemRequest::read(addr, len));
+                issued += 1;
+            }
+            let data = self.response.dequeue(&self.time).unwrap().data.data;
+            packet.extend(data);
+            if reqs[received].2 {
+                let data = Array1::from_vec(std::mem::take(&mut packet));
+                let ce = ChannelElement::new(self.time.tick() + 1, data);
+                self.outputs[desc.port].enqueue(&self.time, ce).unwrap();
+            }
+        }
+    }
+
+    fn store(&self, desc: &DmaDescriptor) {
+        let addrs = desc.addresses();
+        let mut in_flight = 0;
+        for packet in addrs.chunks(self.constants.link_capacity) {
+            let data = self.inputs[desc.port]
+                .dequeue(&self.time)
+                .expect("Store source closed before the descriptor was done")
+                .data;
+            let mut offset = 0;
+            for (addr, len) in runs(packet) {
+                if in_flight == self.constants.outstanding {
+                    self.response.dequeue(&self.time).unwrap();
+                    in_flight -= 1;
+                }
+                let run = data.slice(s![offset..offset + len]).to_owned();
+                self.issue(MemRequest::write(addr, run));
+                offset += len;
+                in_flight += 1;
+            }
+        }
+        for _ in 0..in_flight {
+            self.response.dequeue(&self.time).unwrap();
+        }
+    }
+}
+
+impl<E: DAMType> Context for Dma<E> {
+    fn run(&mut self) {
+        let mut tpkts = Vec::<TracePacket>::new();
+        for desc in self.descriptors.iter() {
+            let start = self.time.tick().time();
+            match desc.direction {
+                DmaTracks::Load => self.load(desc),
+                DmaTracks::Store => self.store(desc),
+            }
+            let end = self.time.tick().time();
+            tpkts.extend(self.evt_slice(desc.direction, [start, end.max(start + 1)]));
+        }
+        if self.constants.track_ids.is_some() {
+            trace::write_trace(
+                format!(
+                    "{prefix}dma_{tid}_.perfetto",
+                    prefix = self.constants.trace_prefix,
+                    tid = self.constants.thread_id
+                )
+                .as_str(),
+                tpkts,
+            );
+        }
+    }
+}
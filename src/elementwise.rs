@@ -0,0 +1,137 @@
+use std::sync::{Arc, Mutex};
+
+use dam::context_tools::*;
+use ndarray::{LinalgScalar, Zip, prelude::*};
+
+/// Function of two packets applied element by element
+#[derive(Copy, Clone, Debug)]
+pub enum ElementwiseOp<E> {
+    Add,
+    Mul,
+    Max,
+    Custom(fn(E, E) -> E),
+}
+
+impl<E: LinalgScalar + PartialOrd> ElementwiseOp<E> {
+    pub fn apply(&self, lhs: &Array1<E>, rhs: &Array1<E>) -> Array1<E> {
+        let func = match self {
+            ElementwiseOp::Add => |a: E, b: E| a + b,
+            ElementwiseOp::Mul => |a: E, b: E| a * b,
+            ElementwiseOp::Max => |a: E, b: E| if a < b { b } else { a },
+            ElementwiseOp::Custom(func) => *func,
+        };
+        Zip::from(lhs).and(rhs).map_collect(|a, b| func(*a, *b))
+    }
+}
+
+/// Alignment of the two input streams
+/// pairs - Packets combined
+/// skewed - Pairs whose packets arrived at different cycles
+/// max_skew - Largest difference between the arrival of two paired packets
+/// unmatched - Packets left on `[lhs, rhs]` after the other stream closed
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SyncStats {
+    pub pairs: usize,
+    pub skewed: usize,
+    pub max_skew: u64,
+    pub unmatched: [usize; 2],
+}
+
+impl SyncStats {
+    /// Both streams had the same length
+    pub fn is_aligned(&self) -> bool {
+        self.unmatched == [0, 0]
+    }
+}
+
+/// Combines the packets of `lhs` and `rhs` pairwise with `op`, e.g. for residual
+/// connections or gating between mesh outputs. A pair is combined once both packets
+/// are in, the slower stream stalls the faster one, and `stats` records by how much.
+#[context_macro]
+pub struct Elementwise<E: Clone> {
+    lhs: Receiver<Array1<E>>,
+    rhs: Receiver<Array1<E>>,
+    output: Sender<Array1<E>>,
+    op: ElementwiseOp<E>,
+    initiation_interval: u64,
+    stats: Arc<Mutex<SyncStats>>,
+}
+
+impl<E> Elementwise<E>
+where
+    E: DAMType + LinalgScalar + PartialOrd,
+{
+    pub fn new(
+        lhs: Receiver<Array1<E>>,
+        rhs: Receiver<Array1<E>>,
+        output: Sender<Array1<E>>,
+        op: ElementwiseOp<E>,
+        initiation_interval: u64,
+        stats: Arc<Mutex<SyncStats>>,
+    ) -> Self {
+        let result = Self {
+            lhs,
+            rhs,
+            output,
+            op,
+            initiation_interval,
+            stats,
+            context_info: Default::default(),
+        };
+        result.lhs.attach_receiver(&result);
+        result.rhs.attach_receiver(&result);
+        result.output.attach_sender(&result);
+        result
+    }
+}
+
+impl<E> Context for Elementwise<E>
+where
+    E: DAMType + LinalgScalar + PartialOrd,
+{
+    fn run(&mut self) {
+        loop {
+            match (self.lhs.dequeue(&self.time), self.rhs.dequeue(&self.time)) {
+                (Ok(lhs), Ok(rhs)) => {
+                    assert!(
+                        lhs.data.len() == rhs.data.len(),
+                        "Packets of {} and {} elements",
+                        lhs.data.len(),
+                        rhs.data.len()
+                    );
+                    let skew = lhs.time.time().abs_diff(rhs.time.time());
+                    {
+                        let mut stats = self.stats.lock().unwrap();
+                        stats.pairs += 1;
+                        if skew > 0 {
+                            stats.skewed += 1;
+                            stats.max_skew = stats.max_skew.max(skew);
+                        }
+                    }
+                    let out = self.op.apply(&lhs.data, &rhs.data);
+                    self.output
+                        .enqueue(&self.time, ChannelElement::new(self.time.tick() + 1, out))
+                        .unwrap()
+                }
+                (Ok(_), Err(_)) => {
+                    let mut left = 1;
+                    while self.lhs.dequeue(&self.time).is_ok() {
+                        left += 1;
+                    }
+                    self.stats.lock().unwrap().unmatched[0] = left;
+                    return;
+                }
+                (Err(_), Ok(_)) => {
+                    let mut left = 1;
+                    while self.rhs.dequeue(&self.time).is_ok() {
+                        left += 1;
+                    }
+                    self.stats.lock().unwrap().unmatched[1] = left;
+                    return;
+                }
+                (Err(_), Err(_)) => return,
+            }
+            self.time.incr_cycles(self.initiation_interval)
+        }
+    }
+}
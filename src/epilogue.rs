@@ -0,0 +1,61 @@
+use dam::context_tools::*;
+use ndarray::prelude::*;
+
+/// Bias and activation of an `Epilogue`
+pub type EpilogueParams<E> = (Array1<E>, fn(E) -> E);
+
+/// Adds `bias` to every packet leaving a mesh column and applies `func` elementwise.
+/// `bias` is the packet sized bias of the column, see `MeshHarness::with_epilogue`.
+#[context_macro]
+pub struct Epilogue<E: Clone> {
+    input: Receiver<Array1<E>>,
+    output: Sender<Array1<E>>,
+    bias: Array1<E>,
+    func: fn(E) -> E,
+    initiation_interval: u64,
+}
+
+impl<E> Epilogue<E>
+where
+    E: DAMType + ndarray::LinalgScalar,
+{
+    pub fn new(
+        input: Receiver<Array1<E>>,
+        output: Sender<Array1<E>>,
+        bias: Array1<E>,
+        func: fn(E) -> E,
+        initiation_interval: u64,
+    ) -> Self {
+        let result = Self {
+            input,
+            output,
+            bias,
+            func,
+            initiation_interval,
+            context_info: Default::default(),
+        };
+        result.input.attach_receiver(&result);
+        result.output.attach_sender(&result);
+        result
+    }
+}
+
+impl<E> Context for Epilogue<E>
+where
+    E: DAMType + ndarray::LinalgScalar,
+{
+    fn run(&mut self) {
+        loop {
+            match self.input.dequeue(&self.time) {
+                Ok(data) => {
+                    let out = (data.data + &self.bias).mapv(self.func);
+                    self.output
+                        .enqueue(&self.time, ChannelElement::new(self.time.tick() + 1, out))
+                        .unwrap()
+                }
+                Err(_) => return,
+            }
+            self.time.incr_cycles(self.initiation_interval)
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::sync::{Arc, Mutex};
+
 use dam::context_tools::*;
 use ndarray::prelude::*;
 use protobuf::{CodedOutputStream, Message};
@@ -23,15 +25,27 @@ pub enum Tracks {
     WrRight = 3,
     Gemm = 4,
 }
+/// How a row's input reaches the nodes of the row
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Dataflow {
+    /// Every node keeps a copy of its input and forwards it to the right
+    Systolic,
+    /// Inputs are broadcast to the whole row over a bus, nodes don't forward
+    Multicast { bus_latency: u64 },
+}
+
 /// Constants for GEMM
 /// link_capacity - Number of elements acceptable in a send/recv
 /// buffer_size - Number of receive msgs acceptable before starting a GEMM
+/// trace_prefix - Prepended to the trace file name, e.g. a subdirectory of the trace dir
 pub struct GemmConstants {
     link_capacity: usize,
     buffer_size: usize,
     thread_id: u32,
     track_ids: [u64; 5],
     num_matmuls: usize,
+    dataflow: Dataflow,
+    trace_prefix: String,
 }
 
 impl GemmConstants {
@@ -48,8 +62,31 @@ impl GemmConstants {
             thread_id,
             track_ids,
             num_matmuls,
+            dataflow: Dataflow::Systolic,
+            trace_prefix: String::new(),
         }
     }
+
+    pub fn with_dataflow(mut self, dataflow: Dataflow) -> Self {
+        self.dataflow = dataflow;
+        self
+    }
+
+    pub fn with_trace_prefix(mut self, trace_prefix: &str) -> Self {
+        self.trace_prefix = trace_prefix.to_string();
+        self
+    }
+}
+
+/// Packets a node moved and the buffers it allocated, indexed like the ports
+/// rd - Packets read from the left and from above
+/// wr - Packets written to the right and down
+/// buffer_elems - Elements of the input, forwarding, partial sum and output buffers
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GemmStats {
+    pub rd: [usize; 2],
+    pub wr: [usize; 2],
+    pub buffer_elems: usize,
 }
 
 /// Models weight stationary systolic/dataflow GEMM
@@ -61,6 +98,8 @@ pub struct Gemm<E: Clone, T: Clone> {
     input: [Receiver<T>; 2],
     output: [Sender<T>; 2],
     initiation_interval: u64,
+    weight_input: Option<Receiver<Array2<E>>>,
+    stats: Option<Arc<Mutex<GemmStats>>>,
 }
 
 impl<E, T> Gemm<E, T>
@@ -110,12 +149,44 @@ where
             input,
             output,
             initiation_interval,
+            weight_input: None,
+            stats: None,
             context_info: Default::default(),
         };
         result.input.iter().for_each(|x| x.attach_receiver(&result));
         result.output.iter().for_each(|x| x.attach_sender(&result));
         result
     }
+
+    /// Weight tiles are loaded from `weight_input` instead of being fixed. A tile is
+    /// loaded before the first matmul and after every `num_matmuls` matmuls, the node
+    /// ends when `weight_input` is closed. `weights` only sets the tile shape.
+    pub fn with_weight_input(mut self, weight_input: Receiver<Array2<E>>) -> Self {
+        weight_input.attach_receiver(&self);
+        self.weight_input = Some(weight_input);
+        self
+    }
+
+    /// Adds the node's traffic and buffers to `stats` when it ends
+    pub fn with_stats(mut self, stats: Arc<Mutex<GemmStats>>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Loads the next weight tile, false when there is none
+    fn load_weights(&mut self) -> bool {
+        let Some(weight_input) = self.weight_input.as_ref() else {
+            return false;
+        };
+        match weight_input.dequeue(&self.time) {
+            Ok(tile) => {
+                assert!(tile.data.shape() == self.weights.shape());
+                self.weights = tile.data;
+                true
+            }
+            Err(_) => false,
+        }
+    }
 }
 
 impl<E, T> Context for Gemm<E, T>
@@ -130,12 +201,18 @@ where
         let ifactor = link_cap / in_features;
         let ofactor = link_cap / out_features;
         let isize = self.constants.buffer_size;
+        let is_forwarding = matches!(self.constants.dataflow, Dataflow::Systolic);
         let mut ibuf1 = Array::<E, _>::zeros([isize, link_cap]);
-        let mut ibuf2 = Array::<E, _>::zeros([isize, link_cap]);
+        // Forwarding buffer. Not needed when the row input is multicast.
+        let mut ibuf2 = Array::<E, _>::zeros([if is_forwarding { isize } else { 0 }, link_cap]);
         assert!((isize * ifactor) % ofactor == 0);
         let osize = (isize * ifactor) / ofactor;
         let mut obuf = Array::<E, _>::zeros([osize, link_cap]);
         let mut cbuf = Array::<E, _>::zeros([osize, link_cap]);
+        let mut counts = GemmStats {
+            buffer_elems: ibuf1.len() + ibuf2.len() + obuf.len() + cbuf.len(),
+            ..Default::default()
+        };
         let mut rd_counter1 = 0;
         let mut rd_counter2 = 0;
         let mut wr_counter1 = 0;
@@ -147,10 +224,18 @@ where
         let mut is_mm_ctrl = false;
         let mut trace = Trace::new();
         let mut file = trace::mk_trace_file(
-            format!("gemm_{tid}_.perfetto", tid = self.constants.thread_id).as_str(),
+            format!(
+                "{prefix}gemm_{tid}_.perfetto",
+                prefix = self.constants.trace_prefix,
+                tid = self.constants.thread_id
+            )
+            .as_str(),
         );
         let mut cos = CodedOutputStream::new(&mut file);
         let mut num_matmuls = 0;
+        if self.weight_input.is_some() && !self.load_weights() {
+            return;
+        }
         loop {
             let mut tpkts = Vec::<TracePacket>::with_capacity(self.constants.track_ids.len() * 2);
             if is_rd_ctrl1 {
@@ -159,6 +244,7 @@ where
                         let row = Array::from_iter(data.data.clone().into_iter());
                         ibuf1.row_mut(rd_counter1).assign(&row);
                         rd_counter1 += 1;
+                        counts.rd[0] += 1;
                         let evt = Tracks::RdLeft;
                         tpkts.extend_from_slice(&self.evt_slice(
                             evt.to_string().as_str(),
@@ -175,6 +261,7 @@ where
                         let row = Array::from_iter(data.data.clone().into_iter());
                         cbuf.row_mut(rd_counter2).assign(&row);
                         rd_counter2 += 1;
+                        counts.rd[1] += 1;
                         let evt = Tracks::RdUp;
                         tpkts.extend_from_slice(&self.evt_slice(
                             evt.to_string().as_str(),
@@ -192,6 +279,7 @@ where
                 let evt = Tracks::WrDown;
                 tpkts.extend_from_slice(&self.evt_slice(evt.to_string().as_str(), evt as usize, 1));
                 wr_counter1 -= 1;
+                counts.wr[1] += 1;
             }
             if is_wr_ctrl2 {
                 let row = ibuf2.row(isize - wr_counter2).to_owned();
@@ -200,6 +288,7 @@ where
                 let evt = Tracks::WrRight;
                 tpkts.extend_from_slice(&self.evt_slice(evt.to_string().as_str(), evt as usize, 1));
                 wr_counter2 -= 1;
+                counts.wr[0] += 1;
             }
             if is_mm_ctrl {
                 let x = ibuf1.to_shape((isize * ifactor, in_features)).unwrap();
@@ -209,8 +298,10 @@ where
                 // println!("{:?}|{:?}", self.constants.thread_id, self.weights);
                 obuf = out.to_shape((osize, link_cap)).unwrap().to_owned();
                 wr_counter1 = osize;
-                wr_counter2 = isize;
-                ibuf2 = ibuf1.clone();
+                if is_forwarding {
+                    wr_counter2 = isize;
+                    ibuf2 = ibuf1.clone();
+                }
                 rd_counter1 = 0;
                 rd_counter2 = 0;
                 let flops = isize * ifactor * in_features * out_features;
@@ -238,7 +329,10 @@ where
                 && wr_counter2 == 0;
             self.time.incr_cycles(self.initiation_interval);
             if num_matmuls == self.constants.num_matmuls && wr_counter1 == 0 && wr_counter2 == 0 {
-                break;
+                if !self.load_weights() {
+                    break;
+                }
+                num_matmuls = 0;
             }
         }
         let dbg_str = format!(
@@ -248,5 +342,13 @@ where
         );
         dbg!(dbg_str);
         cos.flush().unwrap();
+        if let Some(stats) = self.stats.as_ref() {
+            let mut stats = stats.lock().unwrap();
+            (0..2).for_each(|port| {
+                stats.rd[port] += counts.rd[port];
+                stats.wr[port] += counts.wr[port];
+            });
+            stats.buffer_elems += counts.buffer_elems;
+        }
     }
 }
@@ -1,5 +1,28 @@
 use dam::context_tools::*;
 use ndarray::prelude::*;
+use strum::EnumCount;
+
+use crate::trace::{self, perfetto::TracePacket};
+
+#[derive(
+    strum_macros::EnumCount,
+    strum_macros::Display,
+    strum_macros::EnumString,
+    strum_macros::VariantArray,
+    Copy,
+    Clone,
+    Debug,
+)]
+pub enum GemvTracks {
+    Read = 0,
+    Compute = 1,
+    Write = 2,
+}
+
+/// Matrix vector product, `weights · x + biases`, over elements streamed one per cycle.
+/// `batch` vectors share a pass over the weights. Without MAC lanes a pass only costs
+/// `initiation_interval`, with `lanes` it also takes `ceil(rows * cols * batch / lanes)`
+/// cycles of compute.
 #[context_macro]
 pub struct GEMV<T: Clone> {
     weights: Array2<T>,
@@ -7,6 +30,11 @@ pub struct GEMV<T: Clone> {
     input: Receiver<T>,
     output: Sender<T>,
     initiation_interval: u64,
+    batch: usize,
+    lanes: Option<usize>,
+    thread_id: u32,
+    track_ids: Option<[u64; GemvTracks::COUNT]>,
+    trace_prefix: String,
 }
 
 impl<T: DAMType> GEMV<T>
@@ -26,12 +54,65 @@ where
             weights,
             biases,
             initiation_interval,
+            batch: 1,
+            lanes: None,
+            thread_id: 0,
+            track_ids: None,
+            trace_prefix: String::new(),
             context_info: Default::default(),
         };
         result.input.attach_receiver(&result);
         result.output.attach_sender(&result);
         result
     }
+
+    /// Vectors per pass over the weights, the last pass may hold fewer
+    pub fn with_batch(mut self, batch: usize) -> Self {
+        assert!(batch > 0);
+        self.batch = batch;
+        self
+    }
+
+    /// MACs per cycle
+    pub fn with_lanes(mut self, lanes: usize) -> Self {
+        assert!(lanes > 0);
+        self.lanes = Some(lanes);
+        self
+    }
+
+    /// Writes a slice per pass and phase to `gemv_{thread_id}_.perfetto`
+    pub fn with_trace(mut self, thread_id: u32, track_ids: [u64; GemvTracks::COUNT]) -> Self {
+        self.thread_id = thread_id;
+        self.track_ids = Some(track_ids);
+        self
+    }
+
+    /// Prepended to the trace file name, e.g. a subdirectory of the trace dir
+    pub fn with_trace_prefix(mut self, trace_prefix: &str) -> Self {
+        self.trace_prefix = trace_prefix.to_string();
+        self
+    }
+
+    /// Compute cycles of a pass over `batch` vectors
+    pub fn compute_cycles(&self, batch: usize) -> u64 {
+        match self.lanes {
+            Some(lanes) => (self.weights.len() * batch).div_ceil(lanes) as u64,
+            None => 0,
+        }
+    }
+
+    fn evt_slice(&self, evt: GemvTracks, timestamps: [u64; 2]) -> Vec<TracePacket> {
+        match self.track_ids {
+            Some(track_ids) => trace::mk_time_slice(
+                self.thread_id,
+                track_ids[evt as usize],
+                evt.to_string().as_str(),
+                [timestamps[0], timestamps[1].max(timestamps[0] + 1)],
+            )
+            .to_vec(),
+            None => vec![],
+        }
+    }
 }
 
 impl<T> Context for GEMV<T>
@@ -41,32 +122,63 @@ where
     fn run(&mut self) {
         let isize = self.weights.ncols();
         let osize = self.weights.nrows();
-        loop {
-            let mut ibuffer = Vec::with_capacity(isize);
-            for i in 0..isize {
-                match self.input.dequeue(&self.time) {
-                    Ok(data) => {
-                        ibuffer.push(data.data);
+        let mut tpkts = Vec::<TracePacket>::new();
+        'passes: loop {
+            let read_start = self.time.tick().time();
+            let mut ibuffer = Vec::with_capacity(isize * self.batch);
+            for _ in 0..self.batch {
+                for i in 0..isize {
+                    match self.input.dequeue(&self.time) {
+                        Ok(data) => {
+                            ibuffer.push(data.data);
+                        }
+                        Err(_) if i == 0 && ibuffer.is_empty() => break 'passes,
+                        Err(_) if i == 0 => break,
+                        Err(_) => panic!("Nothing to dequeue. Unexpected exit"),
                     }
-                    Err(_) if i == 0 => return,
-                    Err(_) => panic!("Nothing to dequeue. Unexpected exit"),
+                    self.time.incr_cycles(1);
                 }
-                self.time.incr_cycles(1);
             }
+            let nvecs = ibuffer.len() / isize;
+            let compute_start = self.time.tick().time();
+            tpkts.extend(self.evt_slice(GemvTracks::Read, [read_start, compute_start]));
 
-            let input_vec = ndarray::Array::from_vec(ibuffer);
-            let output = self.weights.dot(&input_vec);
+            // Column v holds vector v
+            let input_mat = Array::from_shape_vec((nvecs, isize), ibuffer).unwrap();
+            let output = self.weights.dot(&input_mat.t());
+            self.time.incr_cycles(self.compute_cycles(nvecs));
+            let write_start = self.time.tick().time();
+            tpkts.extend(self.evt_slice(GemvTracks::Compute, [compute_start, write_start]));
 
-            for i in 0..osize {
-                let cur_time = self.time.tick();
-                self.output
-                    .enqueue(
-                        &self.time,
-                        ChannelElement::new(cur_time + 1 + (i as u64), output[i] + self.biases[i]),
-                    )
-                    .unwrap();
+            for v in 0..nvecs {
+                for i in 0..osize {
+                    let cur_time = self.time.tick();
+                    let offset = (v * osize + i) as u64;
+                    self.output
+                        .enqueue(
+                            &self.time,
+                            ChannelElement::new(
+                                cur_time + 1 + offset,
+                                output[[i, v]] + self.biases[i],
+                            ),
+                        )
+                        .unwrap();
+                }
             }
+            let write_end = write_start + (nvecs * osize) as u64;
+            tpkts.extend(self.evt_slice(GemvTracks::Write, [write_start, write_end]));
             self.time.incr_cycles(self.initiation_interval);
         }
+        if self.track_ids.is_some() {
+            trace::write_trace(
+                format!(
+                    "{prefix}gemv_{tid}_.perfetto",
+                    prefix = self.trace_prefix,
+                    tid = self.thread_id
+                )
+                .as_str(),
+                tpkts,
+            );
+        }
     }
 }
@@ -1,6 +1,29 @@
 pub mod actfn;
+pub mod approx;
+pub mod attention;
 pub mod consumer;
+pub mod conv;
+pub mod credit;
+pub mod dma;
+pub mod elementwise;
+pub mod epilogue;
 pub mod gemm;
 pub mod gemv;
+pub mod link;
+pub mod mapper;
+pub mod memory;
+pub mod mesh;
+pub mod mlp;
+pub mod multicast;
+pub mod norm;
+pub mod perf;
 pub mod producer;
+pub mod reduction;
+pub mod router;
+pub mod scheduler;
+pub mod scratchpad;
+pub mod softmax;
+pub mod sweep;
+pub mod systolic;
 pub mod trace;
+pub mod tune;
@@ -0,0 +1,78 @@
+use dam::context_tools::*;
+use ndarray::prelude::*;
+
+/// Physical link parameters
+/// latency - Cycles a packet spends on the wire once it is serialized
+/// width_bytes - Bytes the link moves per cycle
+#[derive(Copy, Clone, Debug)]
+pub struct LinkConfig {
+    latency: u64,
+    width_bytes: usize,
+}
+
+impl LinkConfig {
+    pub fn new(latency: u64, width_bytes: usize) -> Self {
+        assert!(width_bytes > 0);
+        Self {
+            latency,
+            width_bytes,
+        }
+    }
+
+    pub fn latency(&self) -> u64 {
+        self.latency
+    }
+
+    pub fn width_bytes(&self) -> usize {
+        self.width_bytes
+    }
+
+    /// Cycles needed to push `nbytes` through the link. Wide packets are split into
+    /// `width_bytes` flits and sent one flit per cycle.
+    pub fn serialization_cycles(&self, nbytes: usize) -> u64 {
+        nbytes.div_ceil(self.width_bytes).max(1) as u64
+    }
+}
+
+/// Models a point to point link between two mesh nodes.
+/// A packet of `link_capacity` elements occupies the link for
+/// `serialization_cycles` and arrives `latency` cycles after its last flit left.
+#[context_macro]
+pub struct Link<E: Clone> {
+    input: Receiver<Array1<E>>,
+    output: Sender<Array1<E>>,
+    config: LinkConfig,
+}
+
+impl<E: DAMType> Link<E> {
+    pub fn new(input: Receiver<Array1<E>>, output: Sender<Array1<E>>, config: LinkConfig) -> Self {
+        let result = Self {
+            input,
+            output,
+            config,
+            context_info: Default::default(),
+        };
+        result.input.attach_receiver(&result);
+        result.output.attach_sender(&result);
+        result
+    }
+}
+
+impl<E: DAMType> Context for Link<E> {
+    fn run(&mut self) {
+        loop {
+            match self.input.dequeue(&self.time) {
+                Ok(data) => {
+                    let nbytes = data.data.len() * std::mem::size_of::<E>();
+                    self.time
+                        .incr_cycles(self.config.serialization_cycles(nbytes));
+                    let arrival = self.time.tick() + self.config.latency;
+                    self.output
+                        .enqueue(&self.time, ChannelElement::new(arrival, data.data))
+                        .unwrap();
+                }
+                Err(_) => return,
+            }
+        }
+    }
+}
@@ -0,0 +1,398 @@
+use std::ops::Range;
+
+use dam::{
+    context_tools::{DAMType, Receiver, Sender},
+    simulation::{InitializationOptionsBuilder, ProgramBuilder, RunOptions},
+};
+use ndarray::*;
+use strum::EnumCount;
+
+use crate::{
+    epilogue::EpilogueParams,
+    gemm::{Dataflow, Gemm, GemmConstants, Tracks},
+    mesh::{Hop, MeshHarness, MeshSink, mesh_conn, take_left_inputs},
+    systolic::{NodeModel, pe_node},
+    trace,
+};
+
+/// Attaches a context streaming columns `cols` of the padded `X` of a streamed mapping,
+/// in the packet order of `MeshHarness::input_streams`, to a mesh row's sender
+pub type InputFeed<E> = dyn Fn(Range<usize>, Sender<Array1<E>>, &mut ProgramBuilder<'_>);
+
+/// (boundary producers, boundary consumers) of a mesh, as returned by `mesh_conn`
+pub type MeshBoundary<E> = (
+    Vec<[Option<Sender<Array1<E>>>; 2]>,
+    Vec<[Option<Receiver<Array1<E>>>; 2]>,
+);
+
+/// Constants for a tiling
+/// tk - Rows of W held by a node, divides link_capacity
+/// tn - Columns of W held by a node, divides link_capacity
+/// k_passes - Passes over the mesh along K, partial sums are chained between them
+/// n_passes - Passes over the mesh along N, independent of each other
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tiling {
+    pub tk: usize,
+    pub tn: usize,
+    pub k_passes: usize,
+    pub n_passes: usize,
+}
+
+impl Tiling {
+    /// Picks the tiling with the fewest passes, then the least padding.
+    /// A node's tile has to divide the link so that `Gemm` can unpack packets, and a
+    /// matmul's output has to fill whole packets.
+    pub fn new(
+        shape: [usize; 2],
+        dims: [usize; 2],
+        link_capacity: usize,
+        buffer_size: usize,
+    ) -> Self {
+        let [k, n] = shape;
+        let divisors =
+            Vec::from_iter((1..=link_capacity).filter(|d| link_capacity.is_multiple_of(*d)));
+        let mut best: Option<(usize, usize, Tiling)> = None;
+        for &tk in divisors.iter() {
+            for &tn in divisors.iter() {
+                if !(buffer_size * tn).is_multiple_of(tk) {
+                    continue;
+                }
+                let k_passes = k.div_ceil(dims[0] * tk);
+                let n_passes = n.div_ceil(dims[1] * tn);
+                let passes = k_passes * n_passes;
+                let padded = (k_passes * dims[0] * tk) * (n_passes * dims[1] * tn);
+                let tiling = Tiling {
+                    tk,
+                    tn,
+                    k_passes,
+                    n_passes,
+                };
+                match best {
+                    Some((p, w, _)) if (p, w) <= (passes, padded) => (),
+                    _ => best = Some((passes, padded, tiling)),
+                }
+            }
+        }
+        best.unwrap().2
+    }
+}
+
+/// Maps `Y = X W` with `X: [M, K]` and `W: [K, N]` onto a weight stationary mesh.
+/// Node `(r, c)` of pass `(k_tile, n_tile)` holds the `[tk, tn]` block of `W` at
+/// `((k_tile * dims[0] + r) * tk, (n_tile * dims[1] + c) * tn)`. `X`, `W` and `M` are
+/// zero padded to whole tiles and the padding is trimmed from the output.
+pub struct Mapping<E> {
+    x: Option<Array2<E>>,
+    num_inputs: usize,
+    w: Array2<E>,
+    shape: [usize; 3],
+    dims: [usize; 2],
+    link_capacity: usize,
+    buffer_size: usize,
+    tiling: Tiling,
+    epilogue: Option<EpilogueParams<E>>,
+    node_model: NodeModel,
+    initiation_interval: u64,
+    trace_prefix: String,
+}
+
+impl<E> Mapping<E>
+where
+    E: DAMType + LinalgScalar + PartialEq + std::fmt::Debug,
+{
+    pub fn new(
+        x: Array2<E>,
+        w: Array2<E>,
+        dims: [usize; 2],
+        link_capacity: usize,
+        buffer_size: usize,
+    ) -> Self {
+        assert!(x.ncols() == w.nrows());
+        let mut result = Self::streamed(x.nrows(), w, dims, link_capacity, buffer_size);
+        let mut x_pad = Array2::zeros([result.num_inputs, result.w.nrows()]);
+        x_pad.slice_mut(s![..x.nrows(), ..x.ncols()]).assign(&x);
+        result.x = Some(x_pad);
+        result
+    }
+
+    /// Mapping of `m` rows of `X` that are not held in memory but streamed into the
+    /// mesh by the contexts of `run_streamed`
+    pub fn streamed(
+        m: usize,
+        w: Array2<E>,
+        dims: [usize; 2],
+        link_capacity: usize,
+        buffer_size: usize,
+    ) -> Self {
+        let shape = [m, w.nrows(), w.ncols()];
+        let tiling = Tiling::new([shape[1], shape[2]], dims, link_capacity, buffer_size);
+        let rows = Self::rows_per_matmul(link_capacity, buffer_size, tiling.tk);
+        let k_pad = tiling.k_passes * dims[0] * tiling.tk;
+        let n_pad = tiling.n_passes * dims[1] * tiling.tn;
+        let mut w_pad = Array2::zeros([k_pad, n_pad]);
+        w_pad.slice_mut(s![..shape[1], ..shape[2]]).assign(&w);
+        Self {
+            x: None,
+            num_inputs: m.div_ceil(rows) * rows,
+            w: w_pad,
+            shape,
+            dims,
+            link_capacity,
+            buffer_size,
+            tiling,
+            epilogue: None,
+            node_model: NodeModel::Coarse,
+            initiation_interval: 1,
+            trace_prefix: String::new(),
+        }
+    }
+
+    /// Adds `bias`, `[N]`, and applies `func` to the outputs of the last K pass
+    pub fn with_epilogue(mut self, bias: Array1<E>, func: fn(E) -> E) -> Self {
+        assert!(bias.len() == self.shape[2]);
+        let mut b_pad = Array1::zeros(self.w.ncols());
+        b_pad.slice_mut(s![..bias.len()]).assign(&bias);
+        self.epilogue = Some((b_pad, func));
+        self
+    }
+
+    /// Simulates every node as `node_model`, coarse by default
+    pub fn with_node_model(mut self, node_model: NodeModel) -> Self {
+        self.node_model = node_model;
+        self
+    }
+
+    /// Initiation interval of every coarse node, 1 by default
+    pub fn with_initiation_interval(mut self, initiation_interval: u64) -> Self {
+        assert!(initiation_interval > 0);
+        self.initiation_interval = initiation_interval;
+        self
+    }
+
+    /// Prefix of the trace header and of the trace files of every node, see
+    /// `GemmConstants::with_trace_prefix`
+    pub fn with_trace_prefix(mut self, trace_prefix: &str) -> Self {
+        self.trace_prefix = trace_prefix.to_string();
+        self
+    }
+
+    fn rows_per_matmul(link_capacity: usize, buffer_size: usize, tk: usize) -> usize {
+        buffer_size * (link_capacity / tk)
+    }
+
+    pub fn tiling(&self) -> Tiling {
+        self.tiling
+    }
+
+    pub fn dims(&self) -> [usize; 2] {
+        self.dims
+    }
+
+    pub fn link_capacity(&self) -> usize {
+        self.link_capacity
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// `[M, K, N]` of the problem before padding
+    pub fn shape(&self) -> [usize; 3] {
+        self.shape
+    }
+
+    /// Rows of `X` after padding `M` to whole matmuls
+    pub fn num_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    pub fn num_passes(&self) -> usize {
+        self.tiling.k_passes * self.tiling.n_passes
+    }
+
+    /// Matmuls every node runs in one pass
+    pub fn num_matmuls(&self) -> usize {
+        self.num_inputs
+            / Self::rows_per_matmul(self.link_capacity, self.buffer_size, self.tiling.tk)
+    }
+
+    /// Weights of every node in row major node order for pass `(k_tile, n_tile)`
+    pub fn node_weights(&self, k_tile: usize, n_tile: usize) -> Vec<Array2<E>> {
+        let Tiling { tk, tn, .. } = self.tiling;
+        let [rows, cols] = self.dims;
+        Vec::from_iter((0..rows * cols).map(|node_id| {
+            let k0 = (k_tile * rows + node_id / cols) * tk;
+            let n0 = (n_tile * cols + node_id % cols) * tn;
+            self.w.slice(s![k0..k0 + tk, n0..n0 + tn]).to_owned()
+        }))
+    }
+
+    /// Columns of `X` read by the mesh in pass `k_tile`, `[M, dims[0] * tk]`
+    pub fn pass_inputs(&self, k_tile: usize) -> Array2<E> {
+        let width = self.dims[0] * self.tiling.tk;
+        let x = self
+            .x
+            .as_ref()
+            .expect("A streamed mapping holds no activations");
+        x.slice(s![.., k_tile * width..(k_tile + 1) * width])
+            .to_owned()
+    }
+
+    /// Harness of K-tile `k_tile`, its `input_streams` are the per row streams of the pass
+    pub fn harness(&self, k_tile: usize) -> MeshHarness<E> {
+        MeshHarness::new(
+            self.dims,
+            self.link_capacity,
+            self.pass_inputs(k_tile),
+            self.tiling.tn,
+        )
+    }
+
+    /// Adds the nodes of pass `(k_tile, n_tile)` to `ctx` and returns the boundary ports of
+    /// the mesh, see `mesh_conn`. Node `i` traces as thread `first_node + i`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_nodes(
+        &self,
+        k_tile: usize,
+        n_tile: usize,
+        hop: Hop,
+        dataflow: Dataflow,
+        track_ids: &[[u64; Tracks::COUNT]],
+        first_node: usize,
+        ctx: &mut ProgramBuilder<'_>,
+    ) -> MeshBoundary<E> {
+        let (in_conns, out_conns, in_prods, out_cons) =
+            mesh_conn::<E>(self.dims, self.buffer_size, hop, dataflow, ctx);
+        let weights = self.node_weights(k_tile, n_tile);
+        let nodes = weights.into_iter().zip(in_conns.into_iter().zip(out_conns));
+        for (node_id, (wmat, (input, output))) in nodes.enumerate() {
+            if let NodeModel::PeLevel {
+                mac_latency,
+                fifo_depth,
+            } = self.node_model
+            {
+                let link_cap = self.link_capacity;
+                pe_node(wmat, link_cap, input, output, mac_latency, fifo_depth, ctx);
+                continue;
+            }
+            let thread_id = first_node + node_id;
+            ctx.add_child(Gemm::new(
+                wmat,
+                Array1::zeros(self.tiling.tn),
+                GemmConstants::new(
+                    self.link_capacity,
+                    self.buffer_size,
+                    thread_id as u32,
+                    track_ids[thread_id],
+                    self.num_matmuls(),
+                )
+                .with_dataflow(dataflow)
+                .with_trace_prefix(&self.trace_prefix),
+                input,
+                output,
+                self.initiation_interval,
+            ));
+        }
+        (in_prods, out_cons)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn run_pass(
+        &self,
+        k_tile: usize,
+        n_tile: usize,
+        psums: Option<Array2<E>>,
+        hop: Hop,
+        dataflow: Dataflow,
+        track_ids: &[[u64; Tracks::COUNT]],
+        feed: Option<&InputFeed<E>>,
+    ) -> (Array2<E>, u64) {
+        let mut ctx = ProgramBuilder::default();
+        let (mut in_prods, out_cons) =
+            self.add_nodes(k_tile, n_tile, hop, dataflow, track_ids, 0, &mut ctx);
+        let Tiling { tk, tn, .. } = self.tiling;
+        let harness = match feed {
+            Some(feed) => {
+                let width = self.dims[0] * tk;
+                for (row, x_send) in take_left_inputs(&mut in_prods).into_iter().enumerate() {
+                    let c0 = k_tile * width + row * tk;
+                    feed(c0..c0 + tk, x_send, &mut ctx);
+                }
+                MeshHarness::chained(self.dims, self.link_capacity, self.num_inputs, tk, tn)
+            }
+            None => self.harness(k_tile),
+        };
+        let mut harness = harness.with_psums(psums);
+        if let Some((bias, func)) = self.epilogue.as_ref()
+            && k_tile == self.tiling.k_passes - 1
+        {
+            let n_width = self.dims[1] * self.tiling.tn;
+            let bias = bias.slice(s![n_tile * n_width..(n_tile + 1) * n_width]);
+            harness = harness.with_epilogue(bias.to_owned(), *func);
+        }
+        let output = harness.attach(in_prods, out_cons, MeshSink::Collect, &mut ctx);
+        let executed = ctx
+            .initialize(
+                InitializationOptionsBuilder::default()
+                    .run_flavor_inference(true)
+                    .build()
+                    .unwrap(),
+            )
+            .unwrap()
+            .run(RunOptions::default());
+        (output.to_array(), executed.elapsed_cycles().unwrap_or(0))
+    }
+
+    /// Runs every pass one after the other and returns `Y: [M, N]` and the total cycles.
+    /// Each pass overwrites the `Gemm` traces of the previous one.
+    pub fn run(&self, hop: Hop, dataflow: Dataflow) -> (Array2<E>, u64) {
+        assert!(
+            self.x.is_some(),
+            "A streamed mapping runs with run_streamed"
+        );
+        self.run_passes(hop, dataflow, None)
+    }
+
+    /// Same as `run` with the left boundary of every pass fed by `feed`
+    pub fn run_streamed(
+        &self,
+        hop: Hop,
+        dataflow: Dataflow,
+        feed: &InputFeed<E>,
+    ) -> (Array2<E>, u64) {
+        self.run_passes(hop, dataflow, Some(feed))
+    }
+
+    fn run_passes(
+        &self,
+        hop: Hop,
+        dataflow: Dataflow,
+        feed: Option<&InputFeed<E>>,
+    ) -> (Array2<E>, u64) {
+        let num_nodes: usize = self.dims.iter().product();
+        let thread_names = Vec::from_iter((0..num_nodes).map(|n| format!("xpu{n}")));
+        let processes = vec![("xpu".to_string(), thread_names)];
+        let track_ids = trace::get_prefixed_trace_descriptors::<{ Tracks::COUNT }>(
+            processes,
+            num_nodes + 1,
+            num_nodes,
+            &self.trace_prefix,
+        );
+        let n_width = self.dims[1] * self.tiling.tn;
+        let mut y = Array2::zeros([self.num_inputs, self.w.ncols()]);
+        let mut cycles = 0;
+        for n_tile in 0..self.tiling.n_passes {
+            let mut psums = None;
+            for k_tile in 0..self.tiling.k_passes {
+                let (out, pass_cycles) =
+                    self.run_pass(k_tile, n_tile, psums, hop, dataflow, &track_ids, feed);
+                cycles += pass_cycles;
+                psums = Some(out);
+            }
+            y.slice_mut(s![.., n_tile * n_width..(n_tile + 1) * n_width])
+                .assign(&psums.unwrap());
+        }
+        let [m, _, n] = self.shape;
+        (y.slice(s![..m, ..n]).to_owned(), cycles)
+    }
+}
@@ -0,0 +1,409 @@
+use std::sync::{Arc, Mutex};
+
+use dam::context_tools::*;
+use ndarray::prelude::*;
+
+/// Constants for a DRAM/HBM device
+/// channels - Independent channels, each with its own data bus
+/// banks - Banks per channel, each with one open row
+/// row_bytes - Size of a row buffer
+/// bytes_per_cycle - Data bus bandwidth of a channel
+/// burst_bytes - Unit of interleaving and of every data transfer
+/// t_cas, t_rcd, t_rp - Column access, row activate and precharge latencies in cycles
+#[derive(Copy, Clone, Debug)]
+pub struct DramConfig {
+    channels: usize,
+    banks: usize,
+    row_bytes: usize,
+    bytes_per_cycle: usize,
+    burst_bytes: usize,
+    t_cas: u64,
+    t_rcd: u64,
+    t_rp: u64,
+}
+
+impl DramConfig {
+    pub fn new(channels: usize, banks: usize, row_bytes: usize, bytes_per_cycle: usize) -> Self {
+        assert!(channels > 0 && banks > 0 && bytes_per_cycle > 0);
+        let result = Self {
+            channels,
+            banks,
+            row_bytes,
+            bytes_per_cycle,
+            burst_bytes: 64,
+            t_cas: 14,
+            t_rcd: 14,
+            t_rp: 14,
+        };
+        assert!(row_bytes.is_multiple_of(result.burst_bytes));
+        result
+    }
+
+    pub fn with_burst(mut self, burst_bytes: usize) -> Self {
+        assert!(self.row_bytes.is_multiple_of(burst_bytes));
+        self.burst_bytes = burst_bytes;
+        self
+    }
+
+    pub fn with_timing(mut self, t_cas: u64, t_rcd: u64, t_rp: u64) -> Self {
+        self.t_cas = t_cas;
+        self.t_rcd = t_rcd;
+        self.t_rp = t_rp;
+        self
+    }
+
+    /// Bandwidth cap of the whole device
+    pub fn peak_bytes_per_cycle(&self) -> usize {
+        self.channels * self.bytes_per_cycle
+    }
+
+    /// (channel, bank, row) of the burst holding `byte_addr`. Consecutive bursts go to
+    /// consecutive channels, then banks, so streams spread over the device.
+    pub fn map(&self, byte_addr: usize) -> (usize, usize, usize) {
+        let burst = byte_addr / self.burst_bytes;
+        let channel = burst % self.channels;
+        let bank = (burst / self.channels) % self.banks;
+        let bursts_per_row = self.row_bytes / self.burst_bytes;
+        let row = burst / (self.channels * self.banks * bursts_per_row);
+        (channel, bank, row)
+    }
+}
+
+/// Read of `len` elements at `addr`, or write of `data` at `addr` when `data` is set.
+/// Addresses are element indices.
+#[derive(Clone, Debug, Default)]
+pub struct MemRequest<E> {
+    pub addr: usize,
+    pub len: usize,
+    pub data: Option<Array1<E>>,
+}
+
+impl<E: DAMType> MemRequest<E> {
+    pub fn read(addr: usize, len: usize) -> Self {
+        Self {
+            addr,
+            len,
+            data: None,
+        }
+    }
+
+    pub fn write(addr: usize, data: Array1<E>) -> Self {
+        Self {
+            addr,
+            len: data.len(),
+            data: Some(data),
+        }
+    }
+}
+
+impl<E: DAMType> DAMType for MemRequest<E> {
+    fn dam_size(&self) -> usize {
+        2 * usize::BITS as usize + self.data.as_ref().map_or(0, |d| d.dam_size())
+    }
+}
+
+/// Data of a read, empty for a write acknowledgement
+#[derive(Clone, Debug, Default)]
+pub struct MemResponse<E> {
+    pub addr: usize,
+    pub data: Array1<E>,
+}
+
+impl<E: DAMType> DAMType for MemResponse<E> {
+    fn dam_size(&self) -> usize {
+        usize::BITS as usize + self.data.dam_size()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct MemStats {
+    pub reads: usize,
+    pub writes: usize,
+    pub bytes_read: usize,
+    pub bytes_written: usize,
+    pub row_hits: usize,
+    pub row_misses: usize,
+}
+
+impl MemStats {
+    pub fn hit_rate(&self) -> f64 {
+        self.row_hits as f64 / (self.row_hits + self.row_misses).max(1) as f64
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct Bank {
+    open_row: Option<usize>,
+    ready: u64,
+}
+
+/// DRAM/HBM model serving one requester per port. A port accepts one request per
+/// cycle and its responses come back in request order.
+#[context_macro]
+pub struct Memory<E: Clone> {
+    contents: Arc<Mutex<Array1<E>>>,
+    config: DramConfig,
+    requests: Vec<Receiver<MemRequest<E>>>,
+    responses: Vec<Sender<MemResponse<E>>>,
+    stats: Arc<Mutex<MemStats>>,
+}
+
+impl<E: DAMType> Memory<E> {
+    pub fn new(
+        contents: Arc<Mutex<Array1<E>>>,
+        config: DramConfig,
+        requests: Vec<Receiver<MemRequest<E>>>,
+        responses: Vec<Sender<MemResponse<E>>>,
+        stats: Arc<Mutex<MemStats>>,
+    ) -> Self {
+        assert!(requests.len() == responses.len());
+        let result = Self {
+            contents,
+            config,
+            requests,
+            responses,
+            stats,
+            context_info: Default::default(),
+        };
+        result
+            .requests
+            .iter()
+            .for_each(|x| x.attach_receiver(&result));
+        result
+            .responses
+            .iter()
+            .for_each(|x| x.attach_sender(&result));
+        result
+    }
+
+    /// Cycle the last burst of `nbytes` at `byte_addr` is transferred when issued at `now`
+    fn access(
+        &self,
+        byte_addr: usize,
+        nbytes: usize,
+        now: u64,
+        banks: &mut [Bank],
+        bus_free: &mut [u64],
+    ) -> u64 {
+        let cfg = &self.config;
+        let mut stats = self.stats.lock().unwrap();
+        let first = byte_addr / cfg.burst_bytes;
+        let last = (byte_addr + nbytes.max(1)).div_ceil(cfg.burst_bytes);
+        let burst_cycles = cfg.burst_bytes.div_ceil(cfg.bytes_per_cycle) as u64;
+        let mut done = now;
+        for burst in first..last {
+            let (channel, bank, row) = cfg.map(burst * cfg.burst_bytes);
+            let state = &mut banks[channel * cfg.banks + bank];
+            let start = now.max(state.ready);
+            let latency = match state.open_row {
+                Some(open) if open == row => {
+                    stats.row_hits += 1;
+                    cfg.t_cas
+                }
+                Some(_) => {
+                    stats.row_misses += 1;
+                    cfg.t_rp + cfg.t_rcd + cfg.t_cas
+                }
+                None => {
+                    stats.row_misses += 1;
+                    cfg.t_rcd + cfg.t_cas
+                }
+            };
+            state.open_row = Some(row);
+            let data_start = (start + latency).max(bus_free[channel]);
+            state.ready = data_start;
+            bus_free[channel] = data_start + burst_cycles;
+            done = done.max(bus_free[channel]);
+        }
+        done
+    }
+}
+
+impl<E: DAMType> Context for Memory<E> {
+    fn run(&mut self) {
+        let nports = self.requests.len();
+        let elem_bytes = std::mem::size_of::<E>();
+        let mut banks = vec![Bank::default(); self.config.channels * self.config.banks];
+        let mut bus_free = vec![0; self.config.channels];
+        let mut port_done = vec![0; nports];
+        let mut is_open = vec![true; nports];
+        while is_open.iter().any(|x| *x) {
+            // Earliest pending request and earliest time a quiet sender may still send at
+            let (mut served, mut next_req, mut quiet_until) = (false, Time::infinite(), None);
+            for port in 0..nports {
+                if !is_open[port] {
+                    continue;
+                }
+                match self.requests[port].peek() {
+                    PeekResult::Something(req) if req.time <= self.time.tick() => {
+                        served = true;
+                        let req = self.requests[port].dequeue(&self.time).unwrap().data;
+                        let now = self.time.tick().time();
+                        let nbytes = req.len * elem_bytes;
+                        let done = self.access(
+                            req.addr * elem_bytes,
+                            nbytes,
+                            now,
+                            &mut banks,
+                            &mut bus_free,
+                        );
+                        port_done[port] = done.max(port_done[port]);
+                        let data = match req.data {
+                            Some(data) => {
+                                let mut stats = self.stats.lock().unwrap();
+                                stats.writes += 1;
+                                stats.bytes_written += nbytes;
+                                let mut contents = self.contents.lock().unwrap();
+                                contents
+                                    .slice_mut(s![req.addr..req.addr + req.len])
+                                    .assign(&data);
+                                Array1::default(0)
+                            }
+                            None => {
+                                let mut stats = self.stats.lock().unwrap();
+                                stats.reads += 1;
+                                stats.bytes_read += nbytes;
+                                let contents = self.contents.lock().unwrap();
+                                contents.slice(s![req.addr..req.addr + req.len]).to_owned()
+                            }
+                        };
+                        let resp = MemResponse {
+                            addr: req.addr,
+                            data,
+                        };
+                        let ce = ChannelElement::new(Time::new(port_done[port]), resp);
+                        self.responses[port].enqueue(&self.time, ce).unwrap();
+                    }
+                    PeekResult::Something(req) => next_req = next_req.min(req.time),
+                    PeekResult::Nothing(time) => {
+                        quiet_until = Some(quiet_until.map_or(time, |t: Time| t.min(time)))
+                    }
+                    PeekResult::Closed => is_open[port] = false,
+                }
+            }
+            // Idle cycles are skipped rather than counted one by one, so the time only
+            // moves with the requests
+            let now = self.time.tick();
+            let next = quiet_until.map_or(next_req, |t| t.max(now + 1).min(next_req));
+            if served {
+                self.time.incr_cycles(1);
+            } else if !next_req.is_infinite() || quiet_until.is_some_and(|t| t >= now) {
+                self.time.advance(next.max(now + 1));
+            } else if is_open.iter().any(|x| *x) {
+                std::thread::yield_now();
+            }
+        }
+    }
+}
+
+/// Streams `packets`, (addr, len) pairs, from a memory port to `output` with up to
+/// `outstanding` reads in flight. Can feed a mesh boundary in place of a `Producer`.
+#[context_macro]
+pub struct MemReader<E: Clone> {
+    packets: Vec<(usize, usize)>,
+    outstanding: usize,
+    request: Sender<MemRequest<E>>,
+    response: Receiver<MemResponse<E>>,
+    output: Sender<Array1<E>>,
+}
+
+impl<E: DAMType> MemReader<E> {
+    pub fn new(
+        packets: Vec<(usize, usize)>,
+        outstanding: usize,
+        request: Sender<MemRequest<E>>,
+        response: Receiver<MemResponse<E>>,
+        output: Sender<Array1<E>>,
+    ) -> Self {
+        assert!(outstanding > 0);
+        let result = Self {
+            packets,
+            outstanding,
+            request,
+            response,
+            output,
+            context_info: Default::default(),
+        };
+        result.request.attach_sender(&result);
+        result.response.attach_receiver(&result);
+        result.output.attach_sender(&result);
+        result
+    }
+}
+
+impl<E: DAMType> Context for MemReader<E> {
+    fn run(&mut self) {
+        let total = self.packets.len();
+        let mut issued = 0;
+        for received in 0..total {
+            while issued < total && issued - received < self.outstanding {
+                let (addr, len) = self.packets[issued];
+                let ce = ChannelElement::new(self.time.tick() + 1, MemRequest::read(addr, len));
+                self.request.enqueue(&self.time, ce).unwrap();
+                self.time.incr_cycles(1);
+                issued += 1;
+            }
+            let data = self.response.dequeue(&self.time).unwrap().data.data;
+            let ce = ChannelElement::new(self.time.tick() + 1, data);
+            self.output.enqueue(&self.time, ce).unwrap();
+        }
+    }
+}
+
+/// Writes every packet received on `input` to consecutive addresses from `base` with up
+/// to `outstanding` writes in flight. Can drain a mesh boundary in place of a consumer.
+#[context_macro]
+pub struct MemWriter<E: Clone> {
+    base: usize,
+    outstanding: usize,
+    input: Receiver<Array1<E>>,
+    request: Sender<MemRequest<E>>,
+    response: Receiver<MemResponse<E>>,
+}
+
+impl<E: DAMType> MemWriter<E> {
+    pub fn new(
+        base: usize,
+        outstanding: usize,
+        input: Receiver<Array1<E>>,
+        request: Sender<MemRequest<E>>,
+        response: Receiver<MemResponse<E>>,
+    ) -> Self {
+        assert!(outstanding > 0);
+        let result = Self {
+            base,
+            outstanding,
+            input,
+            request,
+            response,
+            context_info: Default::default(),
+        };
+        result.input.attach_receiver(&result);
+        result.request.attach_sender(&result);
+        result.response.attach_receiver(&result);
+        result
+    }
+}
+
+impl<E: DAMType> Context for MemWriter<E> {
+    fn run(&mut self) {
+        let mut addr = self.base;
+        let mut in_flight = 0;
+        while let Ok(data) = self.input.dequeue(&self.time) {
+            if in_flight == self.outstanding {
+                self.response.dequeue(&self.time).unwrap();
+                in_flight -= 1;
+            }
+            let len = data.data.len();
+            let req = MemRequest::write(addr, data.data);
+            let ce = ChannelElement::new(self.time.tick() + 1, req);
+            self.request.enqueue(&self.time, ce).unwrap();
+            self.time.incr_cycles(1);
+            addr += len;
+            in_flight += 1;
+        }
+        for _ in 0..in_flight {
+            self.response.dequeue(&self.time).unwrap();
+        }
+    }
+}
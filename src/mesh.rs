@@ -0,0 +1,497 @@
+use std::sync::{Arc, Mutex};
+
+use dam::{
+    channel::{Receiver, Sender},
+    context_tools::DAMType,
+    simulation::ProgramBuilder,
+    utility_contexts::{CheckerContext, ConsumerContext},
+};
+use ndarray::*;
+
+use crate::{
+    consumer::Collector,
+    credit::{CreditConfig, CreditStats, credit_link},
+    epilogue::{Epilogue, EpilogueParams},
+    gemm::Dataflow,
+    link::{Link, LinkConfig},
+    multicast::{BusStats, Multicast},
+    producer::Producer,
+};
+
+/// Model of a node to node hop
+#[derive(Copy, Clone, Debug)]
+pub enum Hop {
+    /// Bounded channel, one cycle per hop
+    Ideal,
+    /// `Link` context with latency and width
+    Link(LinkConfig),
+    /// Credit flow control. The receiver FIFO holds `buffer_size` packets.
+    Credit { latency: u64, credit_latency: u64 },
+}
+
+/// (senders, receivers, boundary producers, boundary consumers) of one link direction
+type ChanAssignment<T> = (
+    Vec<Sender<Array1<T>>>,
+    Vec<Receiver<Array1<T>>>,
+    Vec<Option<Sender<Array1<T>>>>,
+    Vec<Option<Receiver<Array1<T>>>>,
+);
+
+/// Per node (in_chans, out_chans, in_prods, out_cons). Index 0 is the left/right
+/// port and index 1 is the up/down port.
+pub type MeshPorts<T> = (
+    Vec<[Receiver<Array1<T>>; 2]>,
+    Vec<[Sender<Array1<T>>; 2]>,
+    Vec<[Option<Sender<Array1<T>>>; 2]>,
+    Vec<[Option<Receiver<Array1<T>>>; 2]>,
+);
+
+/// Node to node channel modelled according to `hop`. `node_id` is the sending node.
+fn mk_link<'a, T: DAMType>(
+    buffer_size: usize,
+    hop: Hop,
+    node_id: usize,
+    ctx: &mut ProgramBuilder<'a>,
+) -> (Sender<Array1<T>>, Receiver<Array1<T>>) {
+    match hop {
+        Hop::Ideal => ctx.bounded::<Array1<T>>(buffer_size),
+        Hop::Link(config) => {
+            let (tx, link_rx) = ctx.bounded::<Array1<T>>(buffer_size);
+            let (link_tx, rx) = ctx.bounded::<Array1<T>>(buffer_size);
+            ctx.add_child(Link::new(link_rx, link_tx, config));
+            (tx, rx)
+        }
+        Hop::Credit {
+            latency,
+            credit_latency,
+        } => {
+            let (tx, credit_rx) = ctx.bounded::<Array1<T>>(1);
+            let (credit_tx, rx) = ctx.bounded::<Array1<T>>(1);
+            let config = CreditConfig::new(buffer_size, latency, credit_latency);
+            let stats = Arc::new(Mutex::new(CreditStats::default()));
+            credit_link(
+                credit_rx,
+                credit_tx,
+                config,
+                node_id as u32,
+                None,
+                stats,
+                ctx,
+            );
+            (tx, rx)
+        }
+    }
+}
+
+/// Assign Sender,Receiver channel pair based on Conn matrix
+/// Each row in Conn matrix sums upto a max value of 1
+/// If the row is all zeros, the receiver is a consumer.
+///
+fn assign_chan<'a, T: DAMType>(
+    n: usize,
+    buffer_size: usize,
+    hop: Hop,
+    conn: Array2<bool>,
+    ctx: &mut ProgramBuilder<'a>,
+) -> ChanAssignment<T> {
+    let mut sd_chan = Array1::<Option<Sender<Array1<T>>>>::default(n);
+    let mut rx_chan = Array1::<Option<Receiver<Array1<T>>>>::default(n);
+    let mut rx_cons = Array1::<Option<Receiver<Array1<T>>>>::default(n);
+    let mut sd_prod = Array1::<Option<Sender<Array1<T>>>>::default(n);
+    // let mut total_conns = 0;
+    for s in 0..n {
+        let mut count = 0;
+        for r in 0..n {
+            if conn[(s, r)] {
+                let (tx, rx) = mk_link::<T>(buffer_size, hop, s, ctx);
+                sd_chan[s] = Some(tx);
+                rx_chan[r] = Some(rx);
+                // dbg!(s, r);
+                count += 1;
+            }
+        }
+        if count == 0 {
+            let (tx, rx) = ctx.bounded::<Array1<T>>(buffer_size);
+            sd_chan[s] = Some(tx);
+            rx_cons[s] = Some(rx);
+            count += 1;
+        }
+        assert!(count == 1);
+        count = 0;
+        for r in 0..n {
+            if conn[(r, s)] {
+                count += 1;
+            }
+        }
+        if count == 0 {
+            let (tx, rx) = ctx.bounded::<Array1<T>>(buffer_size);
+            rx_chan[s] = Some(rx);
+            sd_prod[s] = Some(tx);
+            count += 1;
+        }
+        assert!(count == 1);
+    }
+    let sd_chan = Vec::from_iter(sd_chan.into_iter().flatten());
+    let rx_chan = Vec::from_iter(rx_chan.into_iter().flatten());
+    assert!(sd_chan.len() == n && rx_chan.len() == n);
+    let sd_prod = Vec::from_iter(sd_prod);
+    let rx_cons = Vec::from_iter(rx_cons);
+    (sd_chan, rx_chan, sd_prod, rx_cons)
+}
+
+/// Row channels of a multicast mesh. Every row has one bus fed from the left boundary.
+/// Right outputs are never written and end in consumers.
+fn assign_bus<'a, T: DAMType>(
+    dims: [usize; 2],
+    buffer_size: usize,
+    bus_latency: u64,
+    stats: &Arc<Mutex<BusStats>>,
+    ctx: &mut ProgramBuilder<'a>,
+) -> ChanAssignment<T> {
+    let n = dims[0] * dims[1];
+    let mut sd_chan = Vec::with_capacity(n);
+    let mut rx_chan = Vec::with_capacity(n);
+    let mut sd_prod = Vec::with_capacity(n);
+    let rx_cons = Vec::from_iter((0..n).map(|_| None));
+    for row in 0..dims[0] {
+        let (bus_tx, bus_rx) = ctx.bounded::<Array1<T>>(buffer_size);
+        let mut taps = Vec::with_capacity(dims[1]);
+        for _ in 0..dims[1] {
+            let (tx, rx) = ctx.bounded::<Array1<T>>(buffer_size);
+            taps.push(tx);
+            rx_chan.push(rx);
+            let (tx, rx) = ctx.bounded::<Array1<T>>(1);
+            sd_chan.push(tx);
+            ctx.add_child(ConsumerContext::new(rx));
+            sd_prod.push(None);
+        }
+        sd_prod[row * dims[1]] = Some(bus_tx);
+        ctx.add_child(Multicast::new(bus_rx, taps, bus_latency).with_stats(stats.clone()));
+    }
+    (sd_chan, rx_chan, sd_prod, rx_cons)
+}
+
+/// Builds the channels of a `dims[0] x dims[1]` mesh. Partial sums flow down, inputs
+/// flow right or are multicast along the row depending on `dataflow`.
+/// `in_prods` and `out_cons` hold the boundary ports that need a producer or a consumer.
+/// `hop` - Model used for every node to node hop
+pub fn mesh_conn<'a, T: DAMType>(
+    dims: [usize; 2],
+    buffer_size: usize,
+    hop: Hop,
+    dataflow: Dataflow,
+    ctx: &mut ProgramBuilder<'a>,
+) -> MeshPorts<T> {
+    let bus_stats = Arc::new(Mutex::new(BusStats::default()));
+    mesh_conn_with_stats(dims, buffer_size, hop, dataflow, bus_stats, ctx)
+}
+
+/// Same as `mesh_conn`, the row buses of a multicast mesh add their traffic to
+/// `bus_stats`
+pub fn mesh_conn_with_stats<'a, T: DAMType>(
+    dims: [usize; 2],
+    buffer_size: usize,
+    hop: Hop,
+    dataflow: Dataflow,
+    bus_stats: Arc<Mutex<BusStats>>,
+    ctx: &mut ProgramBuilder<'a>,
+) -> MeshPorts<T> {
+    let n = dims.iter().product::<usize>();
+    let mut conn = Array2::<bool>::default([n, n]);
+    for r in 0..n {
+        conn.slice_mut(s![r, r]).assign(&arr0(true));
+    }
+    let conn = conn.to_shape([n, dims[0], dims[1]]).unwrap();
+    let mut send_right = conn.clone();
+    send_right
+        .slice_mut(s![.., .., 1..])
+        .assign(&conn.slice(s![.., .., ..-1]));
+    send_right
+        .slice_mut(s![.., .., 0])
+        .assign(&Array1::<bool>::default([dims[0]]));
+
+    let mut send_down = conn.clone().to_owned();
+    send_down
+        .slice_mut(s![.., 1.., ..])
+        .assign(&conn.slice(s![.., ..-1, ..]));
+    send_down
+        .slice_mut(s![.., 0, ..])
+        .assign(&Array1::<bool>::default([dims[1]]));
+
+    let send_right = send_right.to_shape((n, n)).unwrap().to_owned();
+    let send_down = send_down.to_shape((n, n)).unwrap().to_owned();
+    let (mut rchan, mut lchan, mut rprod, mut lcon) = match dataflow {
+        Dataflow::Systolic => assign_chan::<T>(n, buffer_size, hop, send_right, ctx),
+        Dataflow::Multicast { bus_latency } => {
+            assign_bus::<T>(dims, buffer_size, bus_latency, &bus_stats, ctx)
+        }
+    };
+    let (mut dchan, mut uchan, mut dprod, mut ucon) =
+        assign_chan::<T>(n, buffer_size, hop, send_down, ctx);
+    let mut out_chans = Vec::with_capacity(n);
+    let mut in_chans = Vec::with_capacity(n);
+    let mut in_prods = Vec::with_capacity(n);
+    let mut out_cons = Vec::with_capacity(n);
+    // dbg!(
+    //     "Node to Node Links:",
+    //     dims[0] * (dims[1] - 1) + dims[1] * (dims[0] - 1)
+    // );
+    // dbg!("Prod|Cons links", dims[0] * 2 + dims[1] * 2);
+    // dbg!(rprod.len(), lcon.len(), dprod.len(), ucon.len());
+    (0..n).for_each(|_| {
+        out_chans.push([rchan.remove(0), dchan.remove(0)]);
+        in_chans.push([lchan.remove(0), uchan.remove(0)]);
+        in_prods.push([rprod.remove(0), dprod.remove(0)]);
+        out_cons.push([lcon.remove(0), ucon.remove(0)]);
+    });
+
+    assert!(
+        rchan.is_empty()
+            && uchan.is_empty()
+            && dchan.is_empty()
+            && lchan.is_empty()
+            && rprod.is_empty()
+            && lcon.is_empty()
+            && dprod.is_empty()
+            && ucon.is_empty()
+    );
+    (in_chans, out_chans, in_prods, out_cons)
+}
+
+/// What the bottom boundary of a mesh is attached to
+pub enum MeshSink<E> {
+    /// Drop the outputs
+    Consume,
+    /// Compare against the expected `Y`, `[M, dims[1] * out_features]`
+    Check(Array2<E>),
+    /// Keep the outputs, read them back with `MeshOutput::to_array`
+    Collect,
+    /// Send column `c` into sender `c`, e.g. the left boundary of the next mesh
+    Forward(Vec<Sender<Array1<E>>>),
+}
+
+/// Outputs kept by `MeshSink::Collect`, one buffer per mesh column
+pub struct MeshOutput<E> {
+    columns: Vec<Arc<Mutex<Vec<Array1<E>>>>>,
+    num_inputs: usize,
+}
+
+impl<E: DAMType> MeshOutput<E> {
+    /// Assembles the collected packets into `Y`, `[M, dims[1] * out_features]`
+    pub fn to_array(&self) -> Array2<E> {
+        let blocks = Vec::from_iter(self.columns.iter().map(|col| {
+            let pkts = col.lock().unwrap();
+            let flat = Array::from_iter(pkts.iter().flat_map(|x| x.iter().cloned()));
+            let width = flat.len() / self.num_inputs;
+            flat.into_shape((self.num_inputs, width)).unwrap()
+        }));
+        let views = Vec::from_iter(blocks.iter().map(|x| x.view()));
+        concatenate(Axis(1), &views).unwrap()
+    }
+}
+
+/// Boundary stimulus for a mesh computing `Y = X W + P`.
+/// Row `r` of the mesh reads columns `r * in_features..(r + 1) * in_features` of `X`,
+/// column `c` of the mesh reads and writes columns `c * out_features..(c + 1) * out_features`
+/// of the partial sums `P` and of `Y`. `P` is zero unless set with `with_psums`.
+pub struct MeshHarness<E> {
+    dims: [usize; 2],
+    link_capacity: usize,
+    x: Option<Array2<E>>,
+    num_inputs: usize,
+    in_features: usize,
+    psums: Option<Array2<E>>,
+    epilogue: Option<EpilogueParams<E>>,
+    out_features: usize,
+}
+
+/// Takes the left boundary senders of a mesh, in row order
+pub fn take_left_inputs<E: DAMType>(
+    in_prods: &mut [[Option<Sender<Array1<E>>>; 2]],
+) -> Vec<Sender<Array1<E>>> {
+    Vec::from_iter(in_prods.iter_mut().filter_map(|[x_send, _]| x_send.take()))
+}
+
+impl<E> MeshHarness<E>
+where
+    E: DAMType + ndarray::LinalgScalar + PartialEq,
+{
+    /// x - Activations, `[M, dims[0] * in_features]`
+    pub fn new(dims: [usize; 2], link_capacity: usize, x: Array2<E>, out_features: usize) -> Self {
+        assert!(x.ncols().is_multiple_of(dims[0]));
+        let (num_inputs, in_features) = (x.nrows(), x.ncols() / dims[0]);
+        let mut result = Self::chained(dims, link_capacity, num_inputs, in_features, out_features);
+        result.x = Some(x);
+        result
+    }
+
+    /// Harness of a mesh whose left boundary is fed by another mesh. The left boundary
+    /// senders have to be taken with `take_left_inputs` before `attach`.
+    pub fn chained(
+        dims: [usize; 2],
+        link_capacity: usize,
+        num_inputs: usize,
+        in_features: usize,
+        out_features: usize,
+    ) -> Self {
+        assert!((num_inputs * in_features).is_multiple_of(link_capacity));
+        assert!((num_inputs * out_features).is_multiple_of(link_capacity));
+        Self {
+            dims,
+            link_capacity,
+            x: None,
+            num_inputs,
+            in_features,
+            psums: None,
+            epilogue: None,
+            out_features,
+        }
+    }
+
+    /// Partial sums fed into the top boundary, `[M, dims[1] * out_features]`
+    pub fn with_psums(mut self, psums: Option<Array2<E>>) -> Self {
+        if let Some(p) = psums.as_ref() {
+            assert!(
+                p.nrows() == self.num_inputs() && p.ncols() == self.dims[1] * self.out_features
+            );
+        }
+        self.psums = psums;
+        self
+    }
+
+    /// Adds `bias`, `[dims[1] * out_features]`, to the bottom outputs and applies `func`
+    pub fn with_epilogue(mut self, bias: Array1<E>, func: fn(E) -> E) -> Self {
+        assert!(bias.len() == self.dims[1] * self.out_features);
+        assert!(self.link_capacity.is_multiple_of(self.out_features));
+        self.epilogue = Some((bias, func));
+        self
+    }
+
+    pub fn num_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    pub fn in_features(&self) -> usize {
+        self.in_features
+    }
+
+    /// Bias of column `col` repeated over the rows of one packet
+    fn bias_packet(&self, bias: &Array1<E>, col: usize) -> Array1<E> {
+        let col_bias = bias.slice(s![col * self.out_features..(col + 1) * self.out_features]);
+        Array::from_iter(col_bias.iter().cycle().take(self.link_capacity).cloned())
+    }
+
+    /// Splits `mat` into `parts` column blocks, each streamed as `link_capacity` packets
+    fn split_streams(&self, mat: &Array2<E>, parts: usize) -> Vec<Vec<Array1<E>>> {
+        let width = mat.ncols() / parts;
+        let steps = (mat.nrows() * width) / self.link_capacity;
+        Vec::from_iter((0..parts).map(|p| {
+            let block = mat.slice(s![.., p * width..(p + 1) * width]).to_owned();
+            let block = block.to_shape((steps, self.link_capacity)).unwrap();
+            Vec::from_iter(block.outer_iter().map(|x| x.to_owned()))
+        }))
+    }
+
+    /// Input stream of every mesh row, empty for a chained harness
+    pub fn input_streams(&self) -> Vec<Vec<Array1<E>>> {
+        match self.x.as_ref() {
+            Some(x) => self.split_streams(x, self.dims[0]),
+            None => Vec::new(),
+        }
+    }
+
+    /// Output stream of every mesh column for `y`, `[M, dims[1] * out_features]`
+    pub fn output_streams(&self, y: &Array2<E>) -> Vec<Vec<Array1<E>>> {
+        assert!(y.nrows() == self.num_inputs() && y.ncols() == self.dims[1] * self.out_features);
+        self.split_streams(y, self.dims[1])
+    }
+
+    /// Attaches producers to the left boundary, partial sums to the top boundary and
+    /// consumers to the right boundary. The bottom boundary goes through the epilogue,
+    /// if any, and is attached to `sink`.
+    pub fn attach<'a>(
+        &self,
+        in_prods: Vec<[Option<Sender<Array1<E>>>; 2]>,
+        out_cons: Vec<[Option<Receiver<Array1<E>>>; 2]>,
+        sink: MeshSink<E>,
+        ctx: &mut ProgramBuilder<'a>,
+    ) -> MeshOutput<E> {
+        let mut x_streams = self.input_streams().into_iter();
+        let mut p_streams = self
+            .psums
+            .as_ref()
+            .map(|p| self.output_streams(p).into_iter());
+        let is_collect = matches!(sink, MeshSink::Collect);
+        let (mut y_streams, mut forwards) = match sink {
+            MeshSink::Check(y) => (Some(self.output_streams(&y).into_iter()), None),
+            MeshSink::Forward(senders) => {
+                assert!(senders.len() == self.dims[1]);
+                (None, Some(senders.into_iter()))
+            }
+            _ => (None, None),
+        };
+        let mut output = MeshOutput {
+            columns: Vec::with_capacity(self.dims[1]),
+            num_inputs: self.num_inputs(),
+        };
+        let link_cap = self.link_capacity;
+        let psum_steps = (self.num_inputs() * self.out_features) / link_cap;
+        for (node_id, [x_send, psum_send]) in in_prods.into_iter().enumerate() {
+            if let Some(x_send) = x_send {
+                let stream = x_streams
+                    .next()
+                    .expect("No activations, take the inputs of a chained mesh first");
+                ctx.add_child(Producer::new(|| stream.into_iter(), x_send, node_id, 0));
+            }
+            if let Some(psum_send) = psum_send {
+                match p_streams.as_mut() {
+                    Some(streams) => {
+                        let stream = streams.next().unwrap();
+                        ctx.add_child(Producer::new(|| stream.into_iter(), psum_send, node_id, 0));
+                    }
+                    None => ctx.add_child(Producer::new(
+                        move || (0..psum_steps).map(move |_| Array1::zeros(link_cap)),
+                        psum_send,
+                        node_id,
+                        0,
+                    )),
+                }
+            }
+        }
+        let mut col = 0;
+        for [x_recv, y_recv] in out_cons.into_iter() {
+            if let Some(x_recv) = x_recv {
+                ctx.add_child(ConsumerContext::new(x_recv));
+            }
+            let Some(mut y_recv) = y_recv else {
+                continue;
+            };
+            let (bias, func) = match self.epilogue.as_ref() {
+                Some((bias, func)) => (self.bias_packet(bias, col), *func),
+                None => (Array1::zeros(link_cap), (|x| x) as fn(E) -> E),
+            };
+            col += 1;
+            if let Some(senders) = forwards.as_mut() {
+                let y_send = senders.next().unwrap();
+                ctx.add_child(Epilogue::new(y_recv, y_send, bias, func, 1));
+                continue;
+            }
+            if self.epilogue.is_some() {
+                let (y_send, epi_recv) = ctx.bounded::<Array1<E>>(2);
+                ctx.add_child(Epilogue::new(y_recv, y_send, bias, func, 1));
+                y_recv = epi_recv;
+            }
+            if let Some(streams) = y_streams.as_mut() {
+                let stream = streams.next().unwrap();
+                ctx.add_child(CheckerContext::new(|| stream.into_iter(), y_recv));
+            } else if is_collect {
+                let buffer = Arc::new(Mutex::new(Vec::with_capacity(psum_steps)));
+                output.columns.push(buffer.clone());
+                ctx.add_child(Collector::new(y_recv, buffer));
+            } else {
+                ctx.add_child(ConsumerContext::new(y_recv));
+            }
+        }
+        output
+    }
+}
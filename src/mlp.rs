@@ -0,0 +1,213 @@
+use dam::{
+    context_tools::DAMType,
+    simulation::{InitializationOptionsBuilder, ProgramBuilder, RunOptions},
+};
+use ndarray::*;
+use strum::EnumCount;
+
+use crate::{
+    gemm::{Dataflow, Gemm, GemmConstants, Tracks},
+    mapper::Mapping,
+    mesh::{Hop, MeshHarness, MeshSink, mesh_conn, take_left_inputs},
+    trace,
+};
+
+/// One fully connected layer, `act(X W + bias)`
+/// weights - `[in_features, out_features]`
+/// bias - `[out_features]`
+pub struct Layer<E> {
+    pub weights: Array2<E>,
+    pub bias: Array1<E>,
+    pub activation: fn(E) -> E,
+}
+
+impl<E> Layer<E> {
+    pub fn new(weights: Array2<E>, bias: Array1<E>, activation: fn(E) -> E) -> Self {
+        assert!(weights.ncols() == bias.len());
+        Self {
+            weights,
+            bias,
+            activation,
+        }
+    }
+}
+
+/// How the layers of an MLP are placed
+#[derive(Copy, Clone, Debug)]
+pub enum MlpMapping {
+    /// Every layer runs on the same mesh one after the other, the activations of the
+    /// bottom row are fed back to the left boundary for the next layer
+    SameMesh { dims: [usize; 2] },
+    /// Every layer gets its own `[in_features / tile, out_features / tile]` mesh and the
+    /// bottom row of a mesh feeds the left boundary of the next one
+    Pipeline { tile: usize },
+}
+
+pub struct Mlp<E> {
+    layers: Vec<Layer<E>>,
+    link_capacity: usize,
+    buffer_size: usize,
+    trace_prefix: String,
+}
+
+impl<E> Mlp<E>
+where
+    E: DAMType + LinalgScalar + PartialEq + std::fmt::Debug,
+{
+    pub fn new(layers: Vec<Layer<E>>, link_capacity: usize, buffer_size: usize) -> Self {
+        assert!(!layers.is_empty());
+        for pair in layers.windows(2) {
+            assert!(pair[0].weights.ncols() == pair[1].weights.nrows());
+        }
+        Self {
+            layers,
+            link_capacity,
+            buffer_size,
+            trace_prefix: String::new(),
+        }
+    }
+
+    /// Prefix of the trace header and of the trace files of every node, see
+    /// `GemmConstants::with_trace_prefix`
+    pub fn with_trace_prefix(mut self, trace_prefix: &str) -> Self {
+        self.trace_prefix = trace_prefix.to_string();
+        self
+    }
+
+    /// ndarray reference of the whole network
+    pub fn reference(&self, x: &Array2<E>) -> Array2<E> {
+        self.layers.iter().fold(x.clone(), |x, layer| {
+            (x.dot(&layer.weights) + &layer.bias).mapv(layer.activation)
+        })
+    }
+
+    /// Runs the network on `x`, `[M, in_features]`. Returns the output and total cycles.
+    pub fn run(
+        &self,
+        x: Array2<E>,
+        mapping: MlpMapping,
+        hop: Hop,
+        dataflow: Dataflow,
+    ) -> (Array2<E>, u64) {
+        match mapping {
+            MlpMapping::SameMesh { dims } => {
+                self.layers.iter().fold((x, 0), |(x, cycles), layer| {
+                    let (y, layer_cycles) = Mapping::new(
+                        x,
+                        layer.weights.clone(),
+                        dims,
+                        self.link_capacity,
+                        self.buffer_size,
+                    )
+                    .with_epilogue(layer.bias.clone(), layer.activation)
+                    .with_trace_prefix(&self.trace_prefix)
+                    .run(hop, dataflow);
+                    (y, cycles + layer_cycles)
+                })
+            }
+            MlpMapping::Pipeline { tile } => self.run_pipeline(x, tile, hop, dataflow),
+        }
+    }
+
+    fn run_pipeline(
+        &self,
+        x: Array2<E>,
+        tile: usize,
+        hop: Hop,
+        dataflow: Dataflow,
+    ) -> (Array2<E>, u64) {
+        let link_cap = self.link_capacity;
+        assert!(link_cap.is_multiple_of(tile));
+        let mesh_dims = Vec::from_iter(self.layers.iter().map(|layer| {
+            let [k, n] = [layer.weights.nrows(), layer.weights.ncols()];
+            assert!(k.is_multiple_of(tile) && n.is_multiple_of(tile));
+            [k / tile, n / tile]
+        }));
+        let rows = self.buffer_size * (link_cap / tile);
+        let num_inputs = x.nrows().div_ceil(rows) * rows;
+        let num_matmuls = num_inputs / rows;
+        let mut x_pad = Array2::zeros([num_inputs, x.ncols()]);
+        x_pad.slice_mut(s![..x.nrows(), ..]).assign(&x);
+
+        let num_nodes: usize = mesh_dims.iter().map(|d| d[0] * d[1]).sum();
+        let thread_names = Vec::from_iter((0..num_nodes).map(|n| format!("xpu{n}")));
+        let processes = vec![("xpu".to_string(), thread_names)];
+        let track_ids = trace::get_prefixed_trace_descriptors::<{ Tracks::COUNT }>(
+            processes,
+            num_nodes + 1,
+            num_nodes,
+            &self.trace_prefix,
+        );
+
+        let mut ctx = ProgramBuilder::default();
+        let mut boundaries = Vec::with_capacity(self.layers.len());
+        let mut thread_id = 0;
+        for (layer, dims) in self.layers.iter().zip(mesh_dims.iter()) {
+            let (in_conns, out_conns, in_prods, out_cons) =
+                mesh_conn::<E>(*dims, self.buffer_size, hop, dataflow, &mut ctx);
+            for (node_id, (input, output)) in in_conns.into_iter().zip(out_conns).enumerate() {
+                let (r, c) = (node_id / dims[1], node_id % dims[1]);
+                let wmat = layer
+                    .weights
+                    .slice(s![r * tile..(r + 1) * tile, c * tile..(c + 1) * tile]);
+                ctx.add_child(Gemm::new(
+                    wmat.to_owned(),
+                    Array1::zeros(tile),
+                    GemmConstants::new(
+                        link_cap,
+                        self.buffer_size,
+                        thread_id as u32,
+                        track_ids[thread_id],
+                        num_matmuls,
+                    )
+                    .with_dataflow(dataflow)
+                    .with_trace_prefix(&self.trace_prefix),
+                    input,
+                    output,
+                    1,
+                ));
+                thread_id += 1;
+            }
+            boundaries.push((in_prods, out_cons));
+        }
+        // Attach from the last mesh back so every mesh knows where its outputs go
+        let mut next_inputs = None;
+        let mut output = None;
+        let mut x_pad = Some(x_pad);
+        let layers = self
+            .layers
+            .iter()
+            .zip(mesh_dims)
+            .zip(boundaries)
+            .enumerate();
+        for (idx, ((layer, dims), (mut in_prods, out_cons))) in layers.rev() {
+            let sink = match next_inputs.take() {
+                Some(senders) => MeshSink::Forward(senders),
+                None => MeshSink::Collect,
+            };
+            let harness = if idx == 0 {
+                MeshHarness::new(dims, link_cap, x_pad.take().unwrap(), tile)
+            } else {
+                next_inputs = Some(take_left_inputs(&mut in_prods));
+                MeshHarness::chained(dims, link_cap, num_inputs, tile, tile)
+            };
+            let harness = harness.with_epilogue(layer.bias.clone(), layer.activation);
+            let mesh_output = harness.attach(in_prods, out_cons, sink, &mut ctx);
+            output.get_or_insert(mesh_output);
+        }
+        let executed = ctx
+            .initialize(
+                InitializationOptionsBuilder::default()
+                    .run_flavor_inference(true)
+                    .build()
+                    .unwrap(),
+            )
+            .unwrap()
+            .run(RunOptions::default());
+        let y = output.unwrap().to_array();
+        (
+            y.slice(s![..x.nrows(), ..]).to_owned(),
+            executed.elapsed_cycles().unwrap_or(0),
+        )
+    }
+}
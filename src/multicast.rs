@@ -0,0 +1,70 @@
+use std::sync::{Arc, Mutex};
+
+use dam::context_tools::*;
+
+/// Traffic of the row buses
+/// packets - Packets put on a bus
+/// deliveries - Copies handed to the nodes, `packets` times the fan-out
+#[derive(Copy, Clone, Debug, Default)]
+pub struct BusStats {
+    pub packets: usize,
+    pub deliveries: usize,
+}
+
+/// Broadcasts every packet on `input` to all `outputs` over a shared bus.
+/// The bus carries one packet per cycle, each packet reaches every output
+/// `bus_latency` cycles after it was put on the bus.
+#[context_macro]
+pub struct Multicast<T: Clone> {
+    input: Receiver<T>,
+    outputs: Vec<Sender<T>>,
+    bus_latency: u64,
+    stats: Option<Arc<Mutex<BusStats>>>,
+}
+
+impl<T: DAMType> Multicast<T> {
+    pub fn new(input: Receiver<T>, outputs: Vec<Sender<T>>, bus_latency: u64) -> Self {
+        let result = Self {
+            input,
+            outputs,
+            bus_latency,
+            stats: None,
+            context_info: Default::default(),
+        };
+        result.input.attach_receiver(&result);
+        result.outputs.iter().for_each(|x| x.attach_sender(&result));
+        result
+    }
+
+    /// Adds the packets of this bus to `stats`, which can be shared by several buses
+    pub fn with_stats(mut self, stats: Arc<Mutex<BusStats>>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+}
+
+impl<T: DAMType> Context for Multicast<T> {
+    fn run(&mut self) {
+        let mut num_pkts = 0;
+        loop {
+            match self.input.dequeue(&self.time) {
+                Ok(data) => {
+                    let arrival = self.time.tick() + self.bus_latency;
+                    for output in self.outputs.iter() {
+                        output
+                            .enqueue(&self.time, ChannelElement::new(arrival, data.data.clone()))
+                            .unwrap();
+                    }
+                    num_pkts += 1;
+                }
+                Err(_) => break,
+            }
+            self.time.incr_cycles(1);
+        }
+        if let Some(stats) = &self.stats {
+            let mut stats = stats.lock().unwrap();
+            stats.packets += num_pkts;
+            stats.deliveries += num_pkts * self.outputs.len();
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use dam::context_tools::*;
+use ndarray::{NdFloat, prelude::*};
+
+/// Which statistics a row is normalized with
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NormKind {
+    /// `(x - mean) / sqrt(var + eps) * gamma + beta`, reduces the sum and then the squared
+    /// deviations from the mean
+    LayerNorm,
+    /// `x / sqrt(mean(x^2) + eps) * gamma + beta`, reduces the sum of squares only
+    RmsNorm,
+}
+
+/// Constants for a normalization
+/// lanes - Elements reduced or scaled per cycle, sets the throughput
+/// reduction_latency - Cycles of the adder tree after the last element of a reduction
+#[derive(Copy, Clone, Debug)]
+pub struct NormConstants<E> {
+    kind: NormKind,
+    eps: E,
+    lanes: usize,
+    reduction_latency: u64,
+}
+
+impl<E: NdFloat> NormConstants<E> {
+    pub fn new(kind: NormKind, eps: E, lanes: usize, reduction_latency: u64) -> Self {
+        assert!(lanes > 0);
+        Self {
+            kind,
+            eps,
+            lanes,
+            reduction_latency,
+        }
+    }
+
+    /// Normalizes `row` without gamma and beta
+    pub fn normalize(&self, row: ArrayView1<E>) -> Array1<E> {
+        let len = E::from(row.len()).unwrap();
+        let sq_dev = |mean: E| row.fold(E::zero(), |acc, x| acc + (*x - mean) * (*x - mean)) / len;
+        match self.kind {
+            NormKind::LayerNorm => {
+                // Centered before squaring, `E[x^2] - E[x]^2` cancels for large means
+                let mean = row.sum() / len;
+                let var = sq_dev(mean);
+                row.mapv(|x| (x - mean) / (var + self.eps).sqrt())
+            }
+            NormKind::RmsNorm => {
+                let mean_sq = sq_dev(E::zero());
+                row.mapv(|x| x / (mean_sq + self.eps).sqrt())
+            }
+        }
+    }
+
+    /// Sweeps over a row, one per reduction plus one applying the statistics, gamma and
+    /// beta. LayerNorm reduces the squared deviations only once the mean is known.
+    fn sweeps(&self) -> u64 {
+        match self.kind {
+            NormKind::LayerNorm => 3,
+            NormKind::RmsNorm => 2,
+        }
+    }
+
+    /// Cycles between rows of `len` elements
+    pub fn row_interval(&self, len: usize) -> u64 {
+        self.sweeps() * len.div_ceil(self.lanes) as u64
+    }
+
+    /// Cycles from the first element of a row in to the first element out, every
+    /// reduction waits for its adder tree
+    pub fn row_latency(&self, len: usize) -> u64 {
+        self.row_interval(len) + (self.sweeps() - 1) * self.reduction_latency
+    }
+}
+
+/// Normalizes every `[features]` row of `x` and applies `gamma` and `beta`, the ndarray
+/// reference of `Norm`
+pub fn reference<E: NdFloat>(
+    x: &Array2<E>,
+    gamma: &Array1<E>,
+    beta: &Array1<E>,
+    constants: &NormConstants<E>,
+) -> Array2<E> {
+    let mut y = x.clone();
+    for mut row in y.rows_mut() {
+        let out = constants.normalize(row.view()) * gamma + beta;
+        row.assign(&out);
+    }
+    y
+}
+
+/// LayerNorm or RMSNorm over the rows received on `input`. Rows are pipelined: a new row
+/// starts every `row_interval` cycles and leaves `row_latency` cycles after it started.
+#[context_macro]
+pub struct Norm<E: Clone> {
+    input: Receiver<Array1<E>>,
+    output: Sender<Array1<E>>,
+    gamma: Array1<E>,
+    beta: Array1<E>,
+    constants: NormConstants<E>,
+}
+
+impl<E: DAMType + NdFloat> Norm<E> {
+    pub fn new(
+        input: Receiver<Array1<E>>,
+        output: Sender<Array1<E>>,
+        gamma: Array1<E>,
+        beta: Array1<E>,
+        constants: NormConstants<E>,
+    ) -> Self {
+        assert!(gamma.len() == beta.len());
+        let result = Self {
+            input,
+            output,
+            gamma,
+            beta,
+            constants,
+            context_info: Default::default(),
+        };
+        result.input.attach_receiver(&result);
+        result.output.attach_sender(&result);
+        result
+    }
+}
+
+impl<E: DAMType + NdFloat> Context for Norm<E> {
+    fn run(&mut self) {
+        let features = self.gamma.len();
+        loop {
+            match self.input.dequeue(&self.time) {
+                Ok(data) => {
+                    assert!(data.data.len() == features);
+                    let out = self.constants.normalize(data.data.view()) * &self.gamma + &self.beta;
+                    let ready = self.time.tick() + self.constants.row_latency(features);
+                    self.output
+                        .enqueue(&self.time, ChannelElement::new(ready, out))
+                        .unwrap()
+                }
+                Err(_) => return,
+            }
+            self.time.incr_cycles(self.constants.row_interval(features))
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use ndarray::prelude::*;
+
+use crate::{
+    gemm::Dataflow,
+    mapper::{Mapping, Tiling},
+    mesh::Hop,
+};
+
+/// Constants of a mesh GEMM, the `GemmConstants` shared by every node plus the mesh and
+/// the workload
+/// shape - `[M, K, N]` of `Y = X W`
+/// initiation_interval - Cycles of one iteration of a node's loop
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PerfParams {
+    pub shape: [usize; 3],
+    pub dims: [usize; 2],
+    pub link_capacity: usize,
+    pub buffer_size: usize,
+    pub initiation_interval: u64,
+    pub dataflow: Dataflow,
+}
+
+impl PerfParams {
+    pub fn new(
+        shape: [usize; 3],
+        dims: [usize; 2],
+        link_capacity: usize,
+        buffer_size: usize,
+    ) -> Self {
+        Self {
+            shape,
+            dims,
+            link_capacity,
+            buffer_size,
+            initiation_interval: 1,
+            dataflow: Dataflow::Systolic,
+        }
+    }
+
+    pub fn with_initiation_interval(mut self, initiation_interval: u64) -> Self {
+        assert!(initiation_interval > 0);
+        self.initiation_interval = initiation_interval;
+        self
+    }
+
+    pub fn with_dataflow(mut self, dataflow: Dataflow) -> Self {
+        self.dataflow = dataflow;
+        self
+    }
+
+    /// Mapping of `x` and `w` onto the mesh with these constants
+    pub fn mapping(&self, x: Array2<f64>, w: Array2<f64>) -> Mapping<f64> {
+        assert!(x.dim() == (self.shape[0], self.shape[1]) && w.ncols() == self.shape[2]);
+        Mapping::new(x, w, self.dims, self.link_capacity, self.buffer_size)
+            .with_initiation_interval(self.initiation_interval)
+    }
+}
+
+/// Packets of `link_capacity` elements moved over every kind of link, summed over passes
+/// inputs - Left boundary to the first column
+/// forwarded - Node to node, to the right
+/// bus - Deliveries of a row bus under multicast
+/// psums_in - Top boundary to the first row
+/// vertical - Node to node, downwards
+/// outputs - Last row to the bottom boundary
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct LinkTraffic {
+    pub inputs: usize,
+    pub forwarded: usize,
+    pub bus: usize,
+    pub psums_in: usize,
+    pub vertical: usize,
+    pub outputs: usize,
+}
+
+impl LinkTraffic {
+    pub fn node_to_node(&self) -> usize {
+        self.forwarded + self.vertical
+    }
+
+    pub fn total(&self) -> usize {
+        self.inputs + self.forwarded + self.bus + self.psums_in + self.vertical + self.outputs
+    }
+}
+
+/// Prediction of the analytical model
+/// pass_cycles - Cycles of one pass, all passes take the same time
+/// useful_macs - MACs on unpadded weights, per node in row major order
+/// peak_macs - MACs a node does per cycle
+/// executed_macs - MACs of all nodes, padding included
+/// buffer_elems - Elements a node keeps in SRAM, its packet buffers and its weights
+#[derive(Clone, Debug, PartialEq)]
+pub struct Estimate {
+    pub cycles: u64,
+    pub pass_cycles: u64,
+    pub tiling: Tiling,
+    pub useful_macs: Vec<usize>,
+    pub peak_macs: usize,
+    pub executed_macs: usize,
+    pub buffer_elems: usize,
+    pub traffic: LinkTraffic,
+}
+
+impl Estimate {
+    /// Fraction of the peak MACs of every node doing useful work over `cycles`
+    pub fn utilization(&self, cycles: u64) -> Vec<f64> {
+        let peak = (self.peak_macs as u64 * cycles.max(1)) as f64;
+        Vec::from_iter(self.useful_macs.iter().map(|macs| *macs as f64 / peak))
+    }
+
+    pub fn mean_utilization(&self, cycles: u64) -> f64 {
+        let utilization = self.utilization(cycles);
+        utilization.iter().sum::<f64>() / utilization.len() as f64
+    }
+}
+
+/// Predicts the cycles of `Mapping::run` with ideal hops.
+/// A coarse node reads `buffer_size` input packets and its partial sum packets one per
+/// initiation interval, then spends a cycle per buffered row on the matmul, so a matmul
+/// takes `P = II * max(reads) + rows - 1 + II`. Outputs are written while the next
+/// matmul's inputs are read. A node only starts once its left and upper neighbours
+/// forwarded a whole matmul, which delays the last node by `P + 1` per hop. Multicast
+/// removes the hops along a row but puts the bus in front of every row.
+pub fn estimate(params: &PerfParams) -> Estimate {
+    let [m, k, n] = params.shape;
+    let [rows, cols] = params.dims;
+    let (link_cap, ii) = (params.link_capacity, params.initiation_interval);
+    let mapping = Mapping::streamed(
+        m,
+        Array2::<f64>::zeros([k, n]),
+        params.dims,
+        link_cap,
+        params.buffer_size,
+    );
+    let tiling = mapping.tiling();
+    let Tiling { tk, tn, .. } = tiling;
+    let num_inputs = mapping.num_inputs();
+    let num_passes = mapping.num_passes();
+
+    let isize = params.buffer_size;
+    let mm_rows = isize * (link_cap / tk);
+    let osize = mm_rows * tn / link_cap;
+    let period = ii * isize.max(osize) as u64 + mm_rows as u64 - 1 + ii;
+    let (writes, hops, bus_latency, fwd_size) = match params.dataflow {
+        Dataflow::Systolic => (isize.max(osize), rows - 1 + cols - 1, 0, isize),
+        Dataflow::Multicast { bus_latency } => (osize, rows - 1, bus_latency, 0),
+    };
+    let pass_cycles = mapping.num_matmuls() as u64 * period
+        + ii * writes as u64
+        + 1
+        + bus_latency
+        + hops as u64 * (period + 1);
+
+    // Packets per boundary port over all passes
+    let x_pkts = num_passes * num_inputs * tk / link_cap;
+    let y_pkts = num_passes * num_inputs * tn / link_cap;
+    let is_systolic = matches!(params.dataflow, Dataflow::Systolic);
+    let traffic = LinkTraffic {
+        inputs: rows * x_pkts,
+        forwarded: if is_systolic {
+            rows * (cols - 1) * x_pkts
+        } else {
+            0
+        },
+        bus: if is_systolic { 0 } else { rows * cols * x_pkts },
+        psums_in: cols * y_pkts,
+        vertical: (rows - 1) * cols * y_pkts,
+        outputs: cols * y_pkts,
+    };
+
+    // Weights of a node that are not padding, over all passes
+    let real = |total: usize, tile: usize, first: usize| total.saturating_sub(first).min(tile);
+    let useful_macs = Vec::from_iter((0..rows * cols).map(|node_id| {
+        let (r, c) = (node_id / cols, node_id % cols);
+        let k_real: usize = (0..tiling.k_passes)
+            .map(|kt| real(k, tk, (kt * rows + r) * tk))
+            .sum();
+        let n_real: usize = (0..tiling.n_passes)
+            .map(|nt| real(n, tn, (nt * cols + c) * tn))
+            .sum();
+        m * k_real * n_real
+    }));
+    Estimate {
+        cycles: pass_cycles * num_passes as u64,
+        pass_cycles,
+        tiling,
+        useful_macs,
+        peak_macs: tk * tn,
+        executed_macs: rows * cols * num_passes * num_inputs * tk * tn,
+        // Input, forwarding, partial sum and output buffers of `Gemm`
+        buffer_elems: (isize + fwd_size + 2 * osize) * link_cap + tk * tn,
+        traffic,
+    }
+}
+
+/// Estimate next to the cycles of the DAM simulation of the same GEMM
+#[derive(Clone, Debug, PartialEq)]
+pub struct Validation {
+    pub estimate: Estimate,
+    pub simulated: u64,
+}
+
+impl Validation {
+    /// Signed error of the model relative to the simulation
+    pub fn relative_error(&self) -> f64 {
+        (self.estimate.cycles as f64 - self.simulated as f64) / self.simulated.max(1) as f64
+    }
+
+    pub fn simulated_utilization(&self) -> Vec<f64> {
+        self.estimate.utilization(self.simulated)
+    }
+}
+
+/// `X` and `W` of `shape` holding small integers, so that `X W` comes out the same in
+/// f64 whatever the order of the sums and can be compared exactly
+pub fn operands(shape: [usize; 3]) -> (Array2<f64>, Array2<f64>) {
+    let [m, k, n] = shape;
+    let x = Array::from_shape_fn([m, k], |(i, j)| ((i * k + j) % 7) as f64 - 3.0);
+    let w = Array::from_shape_fn([k, n], |(i, j)| ((i + 2 * j) % 5) as f64 - 2.0);
+    (x, w)
+}
+
+/// Simulates the GEMM of `params` on `operands` with `hop` between the nodes, checks
+/// the result and compares the cycles against `estimate`
+pub fn validate(params: &PerfParams, hop: Hop) -> Validation {
+    let (x, w) = operands(params.shape);
+    let reference = x.dot(&w);
+    let (y, simulated) = params.mapping(x, w).run(hop, params.dataflow);
+    assert_eq!(y, reference);
+    Validation {
+        estimate: estimate(params),
+        simulated,
+    }
+}
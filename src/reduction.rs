@@ -0,0 +1,192 @@
+use dam::{context_tools::*, simulation::ProgramBuilder};
+use ndarray::{LinalgScalar, prelude::*};
+
+use crate::gemv::GEMV;
+
+/// Adder tree parameters
+/// fan_in - Operands summed by one adder
+/// level_latency - Cycles of one level of adders
+#[derive(Copy, Clone, Debug)]
+pub struct AdderTreeConfig {
+    fan_in: usize,
+    level_latency: u64,
+}
+
+impl AdderTreeConfig {
+    pub fn new(fan_in: usize, level_latency: u64) -> Self {
+        assert!(fan_in > 1);
+        Self {
+            fan_in,
+            level_latency,
+        }
+    }
+
+    /// Levels of adders reducing `inputs` operands
+    pub fn levels(&self, inputs: usize) -> u64 {
+        let (mut levels, mut width) = (0, inputs);
+        while width > 1 {
+            width = width.div_ceil(self.fan_in);
+            levels += 1;
+        }
+        levels
+    }
+
+    /// Cycles from the operands in to the sum out
+    pub fn latency(&self, inputs: usize) -> u64 {
+        self.levels(inputs) * self.level_latency
+    }
+
+    /// Sums `operands` level by level in the order the adders of the tree would
+    pub fn reduce<T: LinalgScalar>(&self, mut operands: Vec<T>) -> T {
+        while operands.len() > 1 {
+            operands = Vec::from_iter(
+                operands
+                    .chunks(self.fan_in)
+                    .map(|c| c.iter().fold(T::zero(), |acc, x| acc + *x)),
+            );
+        }
+        operands.pop().unwrap_or(T::zero())
+    }
+}
+
+/// Pipelined adder tree summing one element of every input per cycle
+#[context_macro]
+pub struct AdderTree<T: Clone> {
+    inputs: Vec<Receiver<T>>,
+    output: Sender<T>,
+    config: AdderTreeConfig,
+}
+
+impl<T: DAMType + LinalgScalar> AdderTree<T> {
+    pub fn new(inputs: Vec<Receiver<T>>, output: Sender<T>, config: AdderTreeConfig) -> Self {
+        assert!(!inputs.is_empty());
+        let result = Self {
+            inputs,
+            output,
+            config,
+            context_info: Default::default(),
+        };
+        result
+            .inputs
+            .iter()
+            .for_each(|x| x.attach_receiver(&result));
+        result.output.attach_sender(&result);
+        result
+    }
+}
+
+impl<T: DAMType + LinalgScalar> Context for AdderTree<T> {
+    fn run(&mut self) {
+        let latency = self.config.latency(self.inputs.len());
+        loop {
+            let mut operands = Vec::with_capacity(self.inputs.len());
+            for (i, input) in self.inputs.iter().enumerate() {
+                match input.dequeue(&self.time) {
+                    Ok(data) => operands.push(data.data),
+                    Err(_) if i == 0 => return,
+                    Err(_) => panic!("Adder tree input {i} closed before the others"),
+                }
+            }
+            let sum = self.config.reduce(operands);
+            let ce = ChannelElement::new(self.time.tick() + latency.max(1), sum);
+            self.output.enqueue(&self.time, ce).unwrap();
+            self.time.incr_cycles(1);
+        }
+    }
+}
+
+/// Splits every vector of `input` into consecutive chunks of `chunks[p]` elements, chunk
+/// `p` goes to `outputs[p]`. The parts are fed in parallel, one element per part per
+/// cycle, so a vector takes as many cycles as its longest chunk.
+#[context_macro]
+pub struct KSplitter<T: Clone> {
+    input: Receiver<Array1<T>>,
+    outputs: Vec<Sender<T>>,
+    chunks: Vec<usize>,
+}
+
+impl<T: DAMType> KSplitter<T> {
+    pub fn new(input: Receiver<Array1<T>>, outputs: Vec<Sender<T>>, chunks: Vec<usize>) -> Self {
+        assert!(outputs.len() == chunks.len());
+        let result = Self {
+            input,
+            outputs,
+            chunks,
+            context_info: Default::default(),
+        };
+        result.input.attach_receiver(&result);
+        result.outputs.iter().for_each(|x| x.attach_sender(&result));
+        result
+    }
+}
+
+impl<T: DAMType> Context for KSplitter<T> {
+    fn run(&mut self) {
+        let starts = Vec::from_iter(self.chunks.iter().scan(0, |start, chunk| {
+            *start += chunk;
+            Some(*start - chunk)
+        }));
+        let longest = self.chunks.iter().copied().max().unwrap_or(0);
+        loop {
+            let vector = match self.input.dequeue(&self.time) {
+                Ok(data) => data.data,
+                Err(_) => return,
+            };
+            assert!(vector.len() == self.chunks.iter().sum::<usize>());
+            for i in 0..longest {
+                let cur_time = self.time.tick();
+                for (part, output) in self.outputs.iter().enumerate() {
+                    if i < self.chunks[part] {
+                        let elem = vector[starts[part] + i].clone();
+                        let ce = ChannelElement::new(cur_time + 1, elem);
+                        output.enqueue(&self.time, ce).unwrap();
+                    }
+                }
+                self.time.incr_cycles(1);
+            }
+        }
+    }
+}
+
+/// GEMV of `weights`, `[N, K]`, split along K over `parts` `GEMV` contexts whose partial
+/// outputs are summed by an `AdderTree`. `input` carries whole vectors, a `KSplitter`
+/// feeds every part its slice in parallel. Every part holds `K / parts` consecutive columns,
+/// give or take one, and part 0 adds the biases. Returns the columns held by every part.
+#[allow(clippy::too_many_arguments)]
+pub fn split_k_gemv<'a, T: DAMType + LinalgScalar>(
+    input: Receiver<Array1<T>>,
+    output: Sender<T>,
+    weights: Array2<T>,
+    biases: Array1<T>,
+    parts: usize,
+    initiation_interval: u64,
+    tree: AdderTreeConfig,
+    ctx: &mut ProgramBuilder<'a>,
+) -> Vec<std::ops::Range<usize>> {
+    let (osize, isize) = weights.dim();
+    assert!(parts > 0 && parts <= isize);
+    let ranges = Vec::from_iter((0..parts).map(|p| p * isize / parts..(p + 1) * isize / parts));
+    let (mut x_sends, mut psum_recvs) = (Vec::new(), Vec::new());
+    for (part, cols) in ranges.iter().enumerate() {
+        let (x_send, x_recv) = ctx.bounded::<T>(cols.len());
+        let (psum_send, psum_recv) = ctx.bounded::<T>(osize);
+        let part_biases = match part {
+            0 => biases.clone(),
+            _ => Array1::zeros(osize),
+        };
+        let part_weights = weights.slice(s![.., cols.clone()]).to_owned();
+        ctx.add_child(GEMV::new(
+            x_recv,
+            psum_send,
+            part_weights,
+            part_biases,
+            initiation_interval,
+        ));
+        x_sends.push(x_send);
+        psum_recvs.push(psum_recv);
+    }
+    let chunks = Vec::from_iter(ranges.iter().map(|r| r.len()));
+    ctx.add_child(KSplitter::new(input, x_sends, chunks));
+    ctx.add_child(AdderTree::new(psum_recvs, output, tree));
+    ranges
+}
@@ -0,0 +1,363 @@
+use std::collections::VecDeque;
+
+use dam::{context_tools::*, simulation::ProgramBuilder};
+use ndarray::prelude::*;
+use strum::EnumCount;
+
+use crate::trace::{self, perfetto::TracePacket};
+
+/// Router ports of a 2D mesh NoC. `Local` connects the node's injection/ejection channels.
+#[derive(
+    strum_macros::EnumCount,
+    strum_macros::Display,
+    strum_macros::EnumString,
+    strum_macros::VariantArray,
+    Copy,
+    Clone,
+    Debug,
+    PartialEq,
+)]
+pub enum Port {
+    Local = 0,
+    North = 1,
+    East = 2,
+    South = 3,
+    West = 4,
+}
+
+/// Packet moving through the NoC. `src` and `dest` are node ids.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Flit<E> {
+    pub src: usize,
+    pub dest: usize,
+    pub payload: Array1<E>,
+}
+
+impl<E: DAMType> Flit<E> {
+    pub fn new(src: usize, dest: usize, payload: Array1<E>) -> Self {
+        Self { src, dest, payload }
+    }
+}
+
+impl<E: DAMType> DAMType for Flit<E> {
+    fn dam_size(&self) -> usize {
+        2 * usize::BITS as usize + self.payload.dam_size()
+    }
+}
+
+/// What a router puts on a router to router link every cycle.
+/// flit - Packet crossing the link this cycle, None for a bubble
+/// ready - Receiving side of the opposite link can accept a flit
+/// quiet - Cycles the sender and, one hop further each cycle back, its neighbourhood have
+/// been idle with their injections closed
+#[derive(Clone, Debug, Default)]
+pub struct Word<E> {
+    flit: Option<Flit<E>>,
+    ready: bool,
+    quiet: usize,
+}
+
+impl<E: DAMType> DAMType for Word<E> {
+    fn dam_size(&self) -> usize {
+        1 + usize::BITS as usize + self.flit.as_ref().map_or(0, |f| f.dam_size())
+    }
+}
+
+/// Picks an output port for a destination node
+pub enum Routing {
+    /// XY routing for `node` in a `dims` mesh. Returns a `Port`.
+    DimensionOrder { dims: [usize; 2], node: usize },
+    /// Output port for every destination node id, a port of the router taking it
+    Table(Vec<usize>),
+}
+
+impl Routing {
+    pub fn route(&self, dest: usize) -> usize {
+        match self {
+            Routing::DimensionOrder { dims, node } => {
+                let (row, col) = (node / dims[1], node % dims[1]);
+                let (drow, dcol) = (dest / dims[1], dest % dims[1]);
+                let port = if dcol > col {
+                    Port::East
+                } else if dcol < col {
+                    Port::West
+                } else if drow > row {
+                    Port::South
+                } else if drow < row {
+                    Port::North
+                } else {
+                    Port::Local
+                };
+                port as usize
+            }
+            Routing::Table(table) => match table.get(dest) {
+                Some(port) => *port,
+                None => panic!("No route to node {dest}, the table has {}", table.len()),
+            },
+        }
+    }
+}
+
+/// Constants for Router
+/// buffer_depth - Flits each input port can hold
+/// diameter - Most hops between two routers of the NoC
+/// track_ids - One trace track per output port
+pub struct RouterConstants {
+    buffer_depth: usize,
+    diameter: usize,
+    thread_id: u32,
+    track_ids: Vec<u64>,
+}
+
+impl RouterConstants {
+    pub fn new(buffer_depth: usize, diameter: usize, thread_id: u32, track_ids: Vec<u64>) -> Self {
+        // A neighbour only learns about free space one cycle late
+        assert!(buffer_depth >= 2);
+        Self {
+            buffer_depth,
+            diameter,
+            thread_id,
+            track_ids,
+        }
+    }
+}
+
+/// Input buffered router with N ports. Port 0 is the local port, ports 1..N lead to
+/// neighbouring routers. Neighbours exchange one `Word` per cycle on every link, so the
+/// routers of a NoC advance in lockstep. One flit per output port per cycle.
+/// A router ends once it has been idle, with its injection closed, for more than
+/// `diameter` cycles and its neighbours report the same. All flits are then in a buffer
+/// of some router, so every router being idle at once means the NoC has drained.
+#[context_macro]
+pub struct Router<E: Clone> {
+    inject: Receiver<Flit<E>>,
+    eject: Sender<Flit<E>>,
+    inputs: Vec<Option<Receiver<Word<E>>>>,
+    outputs: Vec<Option<Sender<Word<E>>>>,
+    routing: Routing,
+    constants: RouterConstants,
+}
+
+impl<E: DAMType> Router<E> {
+    pub fn new(
+        inject: Receiver<Flit<E>>,
+        eject: Sender<Flit<E>>,
+        inputs: Vec<Option<Receiver<Word<E>>>>,
+        outputs: Vec<Option<Sender<Word<E>>>>,
+        routing: Routing,
+        constants: RouterConstants,
+    ) -> Self {
+        assert!(inputs.len() == outputs.len());
+        if let Routing::Table(table) = &routing {
+            assert!(table.iter().all(|port| *port < outputs.len()));
+        }
+        assert!(inputs[0].is_none() && outputs[0].is_none());
+        assert!(constants.track_ids.len() == outputs.len());
+        let result = Self {
+            inject,
+            eject,
+            inputs,
+            outputs,
+            routing,
+            constants,
+            context_info: Default::default(),
+        };
+        result.inject.attach_receiver(&result);
+        result.eject.attach_sender(&result);
+        result
+            .inputs
+            .iter()
+            .flatten()
+            .for_each(|x| x.attach_receiver(&result));
+        result
+            .outputs
+            .iter()
+            .flatten()
+            .for_each(|x| x.attach_sender(&result));
+        result
+    }
+
+    fn evt_slice(&self, evt_name: &str, port: usize) -> [TracePacket; 2] {
+        let cur_time = self.time.tick().time();
+        trace::mk_time_slice(
+            self.constants.thread_id,
+            self.constants.track_ids[port],
+            evt_name,
+            [cur_time, cur_time + 1],
+        )
+    }
+}
+
+impl<E: DAMType> Context for Router<E> {
+    fn run(&mut self) {
+        let nports = self.inputs.len();
+        let depth = self.constants.buffer_depth;
+        let mut ibufs = vec![VecDeque::<Flit<E>>::with_capacity(depth); nports];
+        let mut obufs: Vec<Option<Flit<E>>> = vec![None; nports];
+        let mut ready = vec![true; nports];
+        // Quiet count of every neighbour, a finished one stays quiet
+        let mut nbr_quiet = vec![0; nports];
+        let mut quiet = 0;
+        let mut is_injecting = true;
+        let mut rr_ptr = 0;
+        let mut tpkts = Vec::<TracePacket>::new();
+        loop {
+            // Switch traversal: flits allocated last cycle leave the router
+            for port in 1..nports {
+                if let Some(output) = &self.outputs[port] {
+                    let word = Word {
+                        flit: obufs[port].take(),
+                        ready: ibufs[port].len() + 2 <= depth,
+                        quiet,
+                    };
+                    if output
+                        .enqueue(&self.time, ChannelElement::new(self.time.tick() + 1, word))
+                        .is_err()
+                    {
+                        // Neighbour finished, all traffic has drained
+                        self.outputs[port] = None;
+                    }
+                }
+            }
+            if let Some(flit) = obufs[Port::Local as usize].take() {
+                let ce = ChannelElement::new(self.time.tick() + 1, flit);
+                self.eject.enqueue(&self.time, ce).unwrap();
+            }
+            self.time.incr_cycles(1);
+            // Link traversal: one word from every neighbour
+            for port in 1..nports {
+                if let Some(input) = &self.inputs[port] {
+                    match input.dequeue(&self.time) {
+                        Ok(data) => {
+                            ready[port] = data.data.ready;
+                            nbr_quiet[port] = data.data.quiet;
+                            if let Some(flit) = data.data.flit {
+                                ibufs[port].push_back(flit);
+                            }
+                        }
+                        Err(_) => {
+                            ready[port] = false;
+                            nbr_quiet[port] = usize::MAX;
+                            self.inputs[port] = None;
+                        }
+                    }
+                }
+            }
+            if is_injecting && ibufs[Port::Local as usize].len() < depth {
+                match self.inject.peek() {
+                    PeekResult::Something(data) if data.time <= self.time.tick() => {
+                        let flit = self.inject.dequeue(&self.time).unwrap().data;
+                        ibufs[Port::Local as usize].push_back(flit);
+                    }
+                    PeekResult::Closed => is_injecting = false,
+                    _ => (),
+                }
+            }
+            // Switch allocation: round robin over inputs, one flit per output
+            for offset in 0..nports {
+                let iport = (rr_ptr + offset) % nports;
+                if let Some(flit) = ibufs[iport].front() {
+                    let oport = self.routing.route(flit.dest);
+                    let is_free = obufs[oport].is_none()
+                        && (oport == Port::Local as usize
+                            || (self.outputs[oport].is_some() && ready[oport]));
+                    if is_free {
+                        obufs[oport] = ibufs[iport].pop_front();
+                        let evt = format!("Fwd{iport}->{oport}", iport = iport, oport = oport);
+                        tpkts.extend_from_slice(&self.evt_slice(evt.as_str(), oport));
+                    }
+                }
+            }
+            rr_ptr = (rr_ptr + 1) % nports;
+            let is_empty = ibufs.iter().all(|b| b.is_empty()) && obufs.iter().all(|b| b.is_none());
+            // Routers `d` hops away were idle `quiet - d` cycles ago, so once that reaches
+            // past the diameter they were all idle in the same cycle
+            quiet = if !is_injecting && is_empty {
+                let nbrs = (1..nports).filter(|port| self.outputs[*port].is_some());
+                nbrs.map(|port| nbr_quiet[port])
+                    .min()
+                    .unwrap_or(usize::MAX)
+                    .saturating_add(1)
+            } else {
+                0
+            };
+            if quiet > self.constants.diameter {
+                break;
+            }
+        }
+        trace::write_trace(
+            format!("router_{tid}_.perfetto", tid = self.constants.thread_id).as_str(),
+            tpkts,
+        );
+    }
+}
+
+/// Per node (injection senders, ejection receivers) of a NoC
+pub type NocPorts<E> = (Vec<Sender<Flit<E>>>, Vec<Receiver<Flit<E>>>);
+
+/// Builds a `dims[0] x dims[1]` mesh NoC with dimension order routing.
+/// Returns the injection senders and ejection receivers of every node.
+/// `track_ids` - Per node trace tracks, one for each `Port`
+pub fn noc_conn<'a, E: DAMType>(
+    dims: [usize; 2],
+    buffer_depth: usize,
+    track_ids: Vec<[u64; Port::COUNT]>,
+    ctx: &mut ProgramBuilder<'a>,
+) -> NocPorts<E> {
+    let routing = |node| Routing::DimensionOrder { dims, node };
+    routed_noc_conn(dims, buffer_depth, track_ids, &routing, ctx)
+}
+
+/// Same as `noc_conn` with the routing of every node given by `routing`
+pub fn routed_noc_conn<'a, E: DAMType>(
+    dims: [usize; 2],
+    buffer_depth: usize,
+    track_ids: Vec<[u64; Port::COUNT]>,
+    routing: &dyn Fn(usize) -> Routing,
+    ctx: &mut ProgramBuilder<'a>,
+) -> NocPorts<E> {
+    let n = dims.iter().product::<usize>();
+    assert!(track_ids.len() == n);
+    let diameter = dims[0] - 1 + dims[1] - 1;
+    let mut inputs = Vec::from_iter((0..n).map(|_| Vec::from_iter((0..Port::COUNT).map(|_| None))));
+    let mut outputs =
+        Vec::from_iter((0..n).map(|_| Vec::from_iter((0..Port::COUNT).map(|_| None))));
+    for node in 0..n {
+        let (row, col) = (node / dims[1], node % dims[1]);
+        // Each router owns the links it sends on towards East and South
+        if col + 1 < dims[1] {
+            let (tx, rx) = ctx.bounded::<Word<E>>(2);
+            outputs[node][Port::East as usize] = Some(tx);
+            inputs[node + 1][Port::West as usize] = Some(rx);
+            let (tx, rx) = ctx.bounded::<Word<E>>(2);
+            outputs[node + 1][Port::West as usize] = Some(tx);
+            inputs[node][Port::East as usize] = Some(rx);
+        }
+        if row + 1 < dims[0] {
+            let (tx, rx) = ctx.bounded::<Word<E>>(2);
+            outputs[node][Port::South as usize] = Some(tx);
+            inputs[node + dims[1]][Port::North as usize] = Some(rx);
+            let (tx, rx) = ctx.bounded::<Word<E>>(2);
+            outputs[node + dims[1]][Port::North as usize] = Some(tx);
+            inputs[node][Port::South as usize] = Some(rx);
+        }
+    }
+    let mut inject_sends = Vec::with_capacity(n);
+    let mut eject_recvs = Vec::with_capacity(n);
+    let links = inputs.into_iter().zip(outputs).zip(track_ids);
+    for (node, ((inputs, outputs), tids)) in links.enumerate() {
+        let (inject_send, inject_recv) = ctx.bounded::<Flit<E>>(buffer_depth);
+        let (eject_send, eject_recv) = ctx.bounded::<Flit<E>>(buffer_depth);
+        ctx.add_child(Router::new(
+            inject_recv,
+            eject_send,
+            inputs,
+            outputs,
+            routing(node),
+            RouterConstants::new(buffer_depth, diameter, node as u32, tids.to_vec()),
+        ));
+        inject_sends.push(inject_send);
+        eject_recvs.push(eject_recv);
+    }
+    (inject_sends, eject_recvs)
+}
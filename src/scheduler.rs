@@ -0,0 +1,464 @@
+use std::sync::{Arc, Mutex};
+
+use dam::{
+    context_tools::*,
+    simulation::{InitializationOptionsBuilder, ProgramBuilder, RunOptions},
+    utility_contexts::ConsumerContext,
+};
+use ndarray::prelude::*;
+use strum::EnumCount;
+
+use crate::{
+    gemm::{Dataflow, Gemm, GemmConstants, Tracks},
+    mapper::Mapping,
+    mesh::{Hop, mesh_conn, take_left_inputs},
+    trace,
+};
+
+/// Order in which the `(k_tile, n_tile)` passes of a tiled GEMM are visited
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LoopOrder {
+    /// All K-tiles of an output tile back to back. Partial sums stay on chip, the
+    /// activations are read again for every output tile.
+    OutputTileMajor,
+    /// All output tiles of a K-tile back to back. The activations of a K-tile are read
+    /// once, partial sums are spilled to off-chip memory between K-tiles.
+    KMajor,
+}
+
+impl LoopOrder {
+    pub fn passes(&self, k_passes: usize, n_passes: usize) -> Vec<(usize, usize)> {
+        match self {
+            LoopOrder::OutputTileMajor => {
+                Vec::from_iter((0..n_passes).flat_map(|n| (0..k_passes).map(move |k| (k, n))))
+            }
+            LoopOrder::KMajor => {
+                Vec::from_iter((0..k_passes).flat_map(|k| (0..n_passes).map(move |n| (k, n))))
+            }
+        }
+    }
+}
+
+/// Constants for an off-chip memory port
+/// bytes_per_cycle - Bandwidth of the port
+/// latency - Cycles from reading a packet to it arriving on chip
+#[derive(Copy, Clone, Debug)]
+pub struct OffChipConfig {
+    bytes_per_cycle: usize,
+    latency: u64,
+}
+
+impl OffChipConfig {
+    pub fn new(bytes_per_cycle: usize, latency: u64) -> Self {
+        assert!(bytes_per_cycle > 0);
+        Self {
+            bytes_per_cycle,
+            latency,
+        }
+    }
+
+    /// Cycles the port is busy moving `nbytes`
+    pub fn transfer_cycles(&self, nbytes: usize) -> u64 {
+        nbytes.div_ceil(self.bytes_per_cycle).max(1) as u64
+    }
+}
+
+/// Off-chip traffic of a schedule in bytes
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Traffic {
+    pub weights: usize,
+    pub activations: usize,
+    pub psum_reads: usize,
+    pub psum_writes: usize,
+    pub outputs: usize,
+}
+
+impl Traffic {
+    pub fn total(&self) -> usize {
+        self.weights + self.activations + self.psum_reads + self.psum_writes + self.outputs
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ScheduleReport {
+    pub order: LoopOrder,
+    pub passes: usize,
+    pub cycles: u64,
+    pub traffic: Traffic,
+}
+
+fn packet_bytes<E>(len: usize) -> usize {
+    len * std::mem::size_of::<E>()
+}
+
+/// Streams weight tiles to every node and activations to the left boundary, pass by
+/// pass, through one off-chip port.
+#[context_macro]
+pub struct TileScheduler<E: Clone> {
+    mapping: Arc<Mapping<E>>,
+    order: LoopOrder,
+    offchip: OffChipConfig,
+    weights: Vec<Sender<Array2<E>>>,
+    inputs: Vec<Sender<Array1<E>>>,
+    traffic: Arc<Mutex<Traffic>>,
+}
+
+impl<E> TileScheduler<E>
+where
+    E: DAMType + ndarray::LinalgScalar + PartialEq,
+{
+    pub fn new(
+        mapping: Arc<Mapping<E>>,
+        order: LoopOrder,
+        offchip: OffChipConfig,
+        weights: Vec<Sender<Array2<E>>>,
+        inputs: Vec<Sender<Array1<E>>>,
+        traffic: Arc<Mutex<Traffic>>,
+    ) -> Self {
+        let result = Self {
+            mapping,
+            order,
+            offchip,
+            weights,
+            inputs,
+            traffic,
+            context_info: Default::default(),
+        };
+        result.weights.iter().for_each(|x| x.attach_sender(&result));
+        result.inputs.iter().for_each(|x| x.attach_sender(&result));
+        result
+    }
+
+    fn send<T: DAMType>(&self, output: &Sender<T>, data: T, nbytes: usize, offchip: bool) {
+        let (delay, cycles) = match offchip {
+            true => (self.offchip.latency, self.offchip.transfer_cycles(nbytes)),
+            false => (0, 1),
+        };
+        let ce = ChannelElement::new(self.time.tick() + delay + 1, data);
+        output.enqueue(&self.time, ce).unwrap();
+        self.time.incr_cycles(cycles);
+    }
+}
+
+impl<E> Context for TileScheduler<E>
+where
+    E: DAMType + ndarray::LinalgScalar + PartialEq,
+{
+    fn run(&mut self) {
+        let tiling = self.mapping.tiling();
+        for (k_tile, n_tile) in self.order.passes(tiling.k_passes, tiling.n_passes) {
+            let tiles = self.mapping.node_weights(k_tile, n_tile);
+            for (output, tile) in self.weights.iter().zip(tiles) {
+                let nbytes = packet_bytes::<E>(tile.len());
+                self.traffic.lock().unwrap().weights += nbytes;
+                self.send(output, tile, nbytes, true);
+            }
+            // A K-major schedule keeps the activations of the K-tile in an on-chip buffer
+            let offchip = match self.order {
+                LoopOrder::OutputTileMajor => true,
+                LoopOrder::KMajor => n_tile == 0,
+            };
+            let streams = self.mapping.harness(k_tile).input_streams();
+            for step in 0..streams[0].len() {
+                for (output, stream) in self.inputs.iter().zip(streams.iter()) {
+                    let nbytes = packet_bytes::<E>(stream[step].len());
+                    if offchip {
+                        self.traffic.lock().unwrap().activations += nbytes;
+                    }
+                    self.send(output, stream[step].clone(), nbytes, offchip);
+                }
+            }
+        }
+    }
+}
+
+/// Feeds the top of a mesh column with partial sums, zeros for the first K-tile and
+/// the column's previous outputs from `loopback` afterwards
+#[context_macro]
+pub struct PsumReader<E: Clone> {
+    passes: Vec<(usize, usize)>,
+    packets: [usize; 2],
+    spill: Option<OffChipConfig>,
+    loopback: Receiver<Array1<E>>,
+    output: Sender<Array1<E>>,
+    traffic: Arc<Mutex<Traffic>>,
+}
+
+impl<E> PsumReader<E>
+where
+    E: DAMType + ndarray::LinalgScalar,
+{
+    /// packets - Packets per pass and elements per packet
+    /// spill - Port the partial sums are read from, on chip when `None`
+    pub fn new(
+        passes: Vec<(usize, usize)>,
+        packets: [usize; 2],
+        spill: Option<OffChipConfig>,
+        loopback: Receiver<Array1<E>>,
+        output: Sender<Array1<E>>,
+        traffic: Arc<Mutex<Traffic>>,
+    ) -> Self {
+        let result = Self {
+            passes,
+            packets,
+            spill,
+            loopback,
+            output,
+            traffic,
+            context_info: Default::default(),
+        };
+        result.loopback.attach_receiver(&result);
+        result.output.attach_sender(&result);
+        result
+    }
+}
+
+impl<E> Context for PsumReader<E>
+where
+    E: DAMType + ndarray::LinalgScalar,
+{
+    fn run(&mut self) {
+        let [steps, link_cap] = self.packets;
+        for &(k_tile, _) in self.passes.iter() {
+            for _ in 0..steps {
+                let (data, delay, cycles) = if k_tile == 0 {
+                    (Array1::zeros(link_cap), 0, 1)
+                } else {
+                    let psum = self.loopback.dequeue(&self.time).unwrap().data;
+                    match self.spill {
+                        Some(offchip) => {
+                            let nbytes = packet_bytes::<E>(psum.len());
+                            self.traffic.lock().unwrap().psum_reads += nbytes;
+                            (psum, offchip.latency, offchip.transfer_cycles(nbytes))
+                        }
+                        None => (psum, 0, 1),
+                    }
+                };
+                let ce = ChannelElement::new(self.time.tick() + delay + 1, data);
+                self.output.enqueue(&self.time, ce).unwrap();
+                self.time.incr_cycles(cycles);
+            }
+        }
+    }
+}
+
+/// Drains the bottom of a mesh column. Partial sums go to `loopback` for the next
+/// K-tile, outputs of the last K-tile are written to `outputs[n_tile]`.
+#[context_macro]
+pub struct PsumWriter<E: Clone> {
+    passes: Vec<(usize, usize)>,
+    k_passes: usize,
+    steps: usize,
+    offchip: OffChipConfig,
+    spill: bool,
+    input: Receiver<Array1<E>>,
+    loopback: Sender<Array1<E>>,
+    outputs: Arc<Mutex<Vec<Vec<Array1<E>>>>>,
+    traffic: Arc<Mutex<Traffic>>,
+}
+
+impl<E> PsumWriter<E>
+where
+    E: DAMType + ndarray::LinalgScalar,
+{
+    /// steps - Packets per pass
+    /// spill - Partial sums are written to `offchip` instead of staying on chip
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        passes: Vec<(usize, usize)>,
+        k_passes: usize,
+        steps: usize,
+        offchip: OffChipConfig,
+        spill: bool,
+        input: Receiver<Array1<E>>,
+        loopback: Sender<Array1<E>>,
+        outputs: Arc<Mutex<Vec<Vec<Array1<E>>>>>,
+        traffic: Arc<Mutex<Traffic>>,
+    ) -> Self {
+        let result = Self {
+            passes,
+            k_passes,
+            steps,
+            offchip,
+            spill,
+            input,
+            loopback,
+            outputs,
+            traffic,
+            context_info: Default::default(),
+        };
+        result.input.attach_receiver(&result);
+        result.loopback.attach_sender(&result);
+        result
+    }
+}
+
+impl<E> Context for PsumWriter<E>
+where
+    E: DAMType + ndarray::LinalgScalar,
+{
+    fn run(&mut self) {
+        for &(k_tile, n_tile) in self.passes.iter() {
+            let is_last = k_tile == self.k_passes - 1;
+            for _ in 0..self.steps {
+                let data = self.input.dequeue(&self.time).unwrap().data;
+                let nbytes = packet_bytes::<E>(data.len());
+                if is_last {
+                    self.traffic.lock().unwrap().outputs += nbytes;
+                    self.outputs.lock().unwrap()[n_tile].push(data);
+                    self.time.incr_cycles(self.offchip.transfer_cycles(nbytes));
+                    continue;
+                }
+                let cycles = if self.spill {
+                    self.traffic.lock().unwrap().psum_writes += nbytes;
+                    self.offchip.transfer_cycles(nbytes)
+                } else {
+                    1
+                };
+                let ce = ChannelElement::new(self.time.tick() + 1, data);
+                self.loopback.enqueue(&self.time, ce).unwrap();
+                self.time.incr_cycles(cycles);
+            }
+        }
+    }
+}
+
+/// Runs a tiled GEMM on one mesh whose nodes load a new weight tile every pass.
+/// Weights and activations come from off-chip memory in `order`, partial sums are
+/// accumulated across K-tiles on chip or through off-chip memory.
+/// Every off-chip port has the bandwidth of `offchip`.
+pub struct TileSchedule<E> {
+    mapping: Arc<Mapping<E>>,
+    order: LoopOrder,
+    offchip: OffChipConfig,
+}
+
+impl<E> TileSchedule<E>
+where
+    E: DAMType + ndarray::LinalgScalar + PartialEq + std::fmt::Debug,
+{
+    pub fn new(mapping: Mapping<E>, order: LoopOrder, offchip: OffChipConfig) -> Self {
+        Self {
+            mapping: Arc::new(mapping),
+            order,
+            offchip,
+        }
+    }
+
+    /// Returns `Y: [M, N]` and the cycles and off-chip traffic of the schedule
+    pub fn run(&self, hop: Hop, dataflow: Dataflow) -> (Array2<E>, ScheduleReport) {
+        let mapping = &self.mapping;
+        let dims = mapping.dims();
+        let tiling = mapping.tiling();
+        let link_cap = mapping.link_capacity();
+        let num_nodes: usize = dims.iter().product();
+        let thread_names = Vec::from_iter((0..num_nodes).map(|n| format!("xpu{n}")));
+        let processes = vec![("xpu".to_string(), thread_names)];
+        let track_ids =
+            trace::get_trace_descriptors::<{ Tracks::COUNT }>(processes, num_nodes + 1, num_nodes);
+
+        let mut ctx = ProgramBuilder::default();
+        let (in_conns, out_conns, mut in_prods, out_cons) =
+            mesh_conn::<E>(dims, mapping.buffer_size(), hop, dataflow, &mut ctx);
+        let mut weight_sends = Vec::with_capacity(num_nodes);
+        for (node_id, (input, output)) in in_conns.into_iter().zip(out_conns).enumerate() {
+            let (weight_send, weight_recv) = ctx.bounded::<Array2<E>>(1);
+            weight_sends.push(weight_send);
+            let gemm = Gemm::new(
+                Array2::zeros([tiling.tk, tiling.tn]),
+                Array1::zeros(tiling.tn),
+                GemmConstants::new(
+                    link_cap,
+                    mapping.buffer_size(),
+                    node_id as u32,
+                    track_ids[node_id],
+                    mapping.num_matmuls(),
+                )
+                .with_dataflow(dataflow),
+                input,
+                output,
+                1,
+            );
+            ctx.add_child(gemm.with_weight_input(weight_recv));
+        }
+
+        let traffic = Arc::new(Mutex::new(Traffic::default()));
+        let passes = self.order.passes(tiling.k_passes, tiling.n_passes);
+        ctx.add_child(TileScheduler::new(
+            mapping.clone(),
+            self.order,
+            self.offchip,
+            weight_sends,
+            take_left_inputs(&mut in_prods),
+            traffic.clone(),
+        ));
+        let spill = matches!(self.order, LoopOrder::KMajor) && tiling.n_passes > 1;
+        let steps = (mapping.num_inputs() * tiling.tn) / link_cap;
+        let psum_sends = in_prods.into_iter().filter_map(|[_, psum_send]| psum_send);
+        let mut loopbacks = Vec::with_capacity(dims[1]);
+        for psum_send in psum_sends {
+            // Partial sums read back for the next K-tile, in pass order
+            let (loopback_send, loopback_recv) = ctx.unbounded::<Array1<E>>();
+            ctx.add_child(PsumReader::new(
+                passes.clone(),
+                [steps, link_cap],
+                spill.then_some(self.offchip),
+                loopback_recv,
+                psum_send,
+                traffic.clone(),
+            ));
+            loopbacks.push(loopback_send);
+        }
+        let mut loopbacks = loopbacks.into_iter();
+        let mut columns = Vec::with_capacity(dims[1]);
+        for [x_recv, y_recv] in out_cons.into_iter() {
+            if let Some(x_recv) = x_recv {
+                ctx.add_child(ConsumerContext::new(x_recv));
+            }
+            if let Some(y_recv) = y_recv {
+                let outputs = Arc::new(Mutex::new(vec![Vec::new(); tiling.n_passes]));
+                columns.push(outputs.clone());
+                ctx.add_child(PsumWriter::new(
+                    passes.clone(),
+                    tiling.k_passes,
+                    steps,
+                    self.offchip,
+                    spill,
+                    y_recv,
+                    loopbacks.next().unwrap(),
+                    outputs,
+                    traffic.clone(),
+                ));
+            }
+        }
+
+        let executed = ctx
+            .initialize(
+                InitializationOptionsBuilder::default()
+                    .run_flavor_inference(true)
+                    .build()
+                    .unwrap(),
+            )
+            .unwrap()
+            .run(RunOptions::default());
+
+        let m_pad = mapping.num_inputs();
+        let mut y = Array2::zeros([m_pad, tiling.n_passes * dims[1] * tiling.tn]);
+        for (col, outputs) in columns.iter().enumerate() {
+            for (n_tile, pkts) in outputs.lock().unwrap().iter().enumerate() {
+                let flat = Array::from_iter(pkts.iter().flat_map(|x| x.iter().cloned()));
+                let block = flat.into_shape((m_pad, tiling.tn)).unwrap();
+                let n0 = (n_tile * dims[1] + col) * tiling.tn;
+                y.slice_mut(s![.., n0..n0 + tiling.tn]).assign(&block);
+            }
+        }
+        let [m, _, n] = mapping.shape();
+        let report = ScheduleReport {
+            order: self.order,
+            passes: passes.len(),
+            cycles: executed.elapsed_cycles().unwrap_or(0),
+            traffic: *traffic.lock().unwrap(),
+        };
+        (y.slice(s![..m, ..n]).to_owned(), report)
+    }
+}
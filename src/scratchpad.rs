@@ -0,0 +1,206 @@
+use std::sync::{Arc, Mutex};
+
+use dam::context_tools::*;
+use ndarray::prelude::*;
+
+use crate::memory::{MemRequest, MemResponse};
+
+/// Bank holding an element address
+#[derive(Copy, Clone, Debug)]
+pub enum BankMapping {
+    /// Consecutive addresses go to consecutive banks
+    Interleaved,
+    /// Every bank holds `bank_size` consecutive addresses
+    Blocked { bank_size: usize },
+    /// Interleaved with the row index, `addr / banks`, xor-ed in to spread strided accesses
+    Xor,
+    /// User mapping of (addr, banks) to a bank
+    Custom(fn(usize, usize) -> usize),
+}
+
+impl BankMapping {
+    pub fn bank(&self, addr: usize, banks: usize) -> usize {
+        match self {
+            BankMapping::Interleaved => addr % banks,
+            BankMapping::Blocked { bank_size } => (addr / bank_size) % banks,
+            BankMapping::Xor => (addr ^ (addr / banks)) % banks,
+            BankMapping::Custom(func) => func(addr, banks) % banks,
+        }
+    }
+}
+
+/// Constants for a scratchpad
+/// banks - Single ported banks, each serves one access per cycle
+/// bank_width - Consecutive elements of one bank read by an access
+/// ports - Requests accepted per cycle over all requesters
+/// latency - Cycles from the last bank access to the response
+#[derive(Copy, Clone, Debug)]
+pub struct ScratchpadConfig {
+    banks: usize,
+    bank_width: usize,
+    ports: usize,
+    latency: u64,
+    mapping: BankMapping,
+}
+
+impl ScratchpadConfig {
+    pub fn new(banks: usize, ports: usize, mapping: BankMapping) -> Self {
+        assert!(banks > 0 && ports > 0);
+        Self {
+            banks,
+            bank_width: 1,
+            ports,
+            latency: 1,
+            mapping,
+        }
+    }
+
+    pub fn with_bank_width(mut self, bank_width: usize) -> Self {
+        assert!(bank_width > 0);
+        self.bank_width = bank_width;
+        self
+    }
+
+    pub fn with_latency(mut self, latency: u64) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Accesses every bank needs for `len` elements at `addr`. The mapping places lines of
+    /// `bank_width` elements, one access reads a whole line.
+    pub fn bank_accesses(&self, addr: usize, len: usize) -> Vec<usize> {
+        let mut accesses = vec![0; self.banks];
+        if len == 0 {
+            return accesses;
+        }
+        for line in addr / self.bank_width..=(addr + len - 1) / self.bank_width {
+            accesses[self.mapping.bank(line, self.banks)] += 1;
+        }
+        accesses
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SpadStats {
+    pub reads: usize,
+    pub writes: usize,
+    pub accesses: usize,
+    /// Cycles requests waited on busy banks
+    pub conflict_cycles: u64,
+    /// Cycles requests waited on a free port
+    pub port_stalls: u64,
+}
+
+/// Banked scratchpad serving one requester per port with the same requests as `Memory`,
+/// so `MemReader`, `MemWriter` and `Dma` can use it. Responses of a requester come back
+/// in request order.
+#[context_macro]
+pub struct Scratchpad<E: Clone> {
+    contents: Arc<Mutex<Array1<E>>>,
+    config: ScratchpadConfig,
+    requests: Vec<Receiver<MemRequest<E>>>,
+    responses: Vec<Sender<MemResponse<E>>>,
+    stats: Arc<Mutex<SpadStats>>,
+}
+
+impl<E: DAMType> Scratchpad<E> {
+    pub fn new(
+        contents: Arc<Mutex<Array1<E>>>,
+        config: ScratchpadConfig,
+        requests: Vec<Receiver<MemRequest<E>>>,
+        responses: Vec<Sender<MemResponse<E>>>,
+        stats: Arc<Mutex<SpadStats>>,
+    ) -> Self {
+        assert!(requests.len() == responses.len());
+        let result = Self {
+            contents,
+            config,
+            requests,
+            responses,
+            stats,
+            context_info: Default::default(),
+        };
+        result
+            .requests
+            .iter()
+            .for_each(|x| x.attach_receiver(&result));
+        result
+            .responses
+            .iter()
+            .for_each(|x| x.attach_sender(&result));
+        result
+    }
+
+    /// Reserves the banks of a request issued at `now`, returns its last access cycle
+    fn access(&self, req: &MemRequest<E>, now: u64, bank_free: &mut [u64]) -> u64 {
+        let accesses = self.config.bank_accesses(req.addr, req.len);
+        let mut stats = self.stats.lock().unwrap();
+        let mut done = now;
+        for (bank, count) in accesses.into_iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let start = now.max(bank_free[bank]);
+            bank_free[bank] = start + count as u64;
+            stats.accesses += count;
+            done = done.max(bank_free[bank]);
+        }
+        // Without conflicts every bank finishes in one cycle
+        stats.conflict_cycles += done.saturating_sub(now + 1);
+        done
+    }
+}
+
+impl<E: DAMType> Context for Scratchpad<E> {
+    fn run(&mut self) {
+        let nports = self.requests.len();
+        let mut bank_free = vec![0; self.config.banks];
+        let mut port_done = vec![0; nports];
+        let mut is_open = vec![true; nports];
+        let mut rr_ptr = 0;
+        while is_open.iter().any(|x| *x) {
+            let mut accepted = 0;
+            for offset in 0..nports {
+                let port = (rr_ptr + offset) % nports;
+                if !is_open[port] {
+                    continue;
+                }
+                match self.requests[port].peek() {
+                    PeekResult::Something(req) if req.time <= self.time.tick() => {
+                        if accepted == self.config.ports {
+                            self.stats.lock().unwrap().port_stalls += 1;
+                            continue;
+                        }
+                        accepted += 1;
+                        let req = self.requests[port].dequeue(&self.time).unwrap().data;
+                        let now = self.time.tick().time();
+                        let done = self.access(&req, now, &mut bank_free) + self.config.latency;
+                        port_done[port] = done.max(port_done[port]);
+                        let range = s![req.addr..req.addr + req.len];
+                        let data = match req.data {
+                            Some(data) => {
+                                self.stats.lock().unwrap().writes += 1;
+                                self.contents.lock().unwrap().slice_mut(range).assign(&data);
+                                Array1::default(0)
+                            }
+                            None => {
+                                self.stats.lock().unwrap().reads += 1;
+                                self.contents.lock().unwrap().slice(range).to_owned()
+                            }
+                        };
+                        let resp = MemResponse {
+                            addr: req.addr,
+                            data,
+                        };
+                        let ce = ChannelElement::new(Time::new(port_done[port]), resp);
+                        self.responses[port].enqueue(&self.time, ce).unwrap();
+                    }
+                    PeekResult::Closed => is_open[port] = false,
+                    _ => (),
+                }
+            }
+            rr_ptr = (rr_ptr + 1) % nports;
+            self.time.incr_cycles(1);
+        }
+    }
+}
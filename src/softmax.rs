@@ -0,0 +1,119 @@
+use dam::context_tools::*;
+use ndarray::{NdFloat, prelude::*};
+
+/// Numerically stable softmax of `row`
+pub fn softmax<E: NdFloat>(row: ArrayView1<E>) -> Array1<E> {
+    let max = row.fold(E::neg_infinity(), |m, x| m.max(*x));
+    let exp = row.mapv(|x| (x - max).exp());
+    let sum = exp.sum();
+    exp / sum
+}
+
+/// How a row is normalized
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SoftmaxMode {
+    /// One sweep for the max, one for the exponentials and their sum, then the division
+    TwoPass,
+    /// One sweep keeping a running max and rescaling the running sum whenever the max
+    /// grows, then the exponentials again for the division
+    Online,
+}
+
+/// Constants for a softmax
+/// lanes - Elements compared, added or divided per cycle
+/// exp_throughput - Exponentials per cycle
+#[derive(Copy, Clone, Debug)]
+pub struct SoftmaxConstants {
+    mode: SoftmaxMode,
+    lanes: usize,
+    exp_throughput: usize,
+}
+
+impl SoftmaxConstants {
+    pub fn new(mode: SoftmaxMode, lanes: usize, exp_throughput: usize) -> Self {
+        assert!(lanes > 0 && exp_throughput > 0);
+        Self {
+            mode,
+            lanes,
+            exp_throughput,
+        }
+    }
+
+    /// Cycles of a sweep over `len` elements computing `exps` exponentials
+    fn sweep(&self, len: usize, exps: usize) -> u64 {
+        len.div_ceil(self.lanes)
+            .max(exps.div_ceil(self.exp_throughput)) as u64
+    }
+
+    /// Normalized `row` and the cycles spent on it
+    pub fn normalize<E: NdFloat>(&self, row: ArrayView1<E>) -> (Array1<E>, u64) {
+        let len = row.len();
+        match self.mode {
+            SoftmaxMode::TwoPass => {
+                let cycles = self.sweep(len, 0) + self.sweep(len, len) + self.sweep(len, 0);
+                (softmax(row), cycles)
+            }
+            SoftmaxMode::Online => {
+                let (mut max, mut sum) = (E::neg_infinity(), E::zero());
+                let mut rescales = 0;
+                for &x in row.iter() {
+                    if x > max {
+                        if max > E::neg_infinity() {
+                            sum *= (max - x).exp();
+                            rescales += 1;
+                        }
+                        max = x;
+                    }
+                    sum += (x - max).exp();
+                }
+                let out = row.mapv(|x| (x - max).exp() / sum);
+                let cycles = self.sweep(len, len + rescales) + self.sweep(len, len);
+                (out, cycles)
+            }
+        }
+    }
+}
+
+/// Normalizes every row received on `input` with a softmax. A row occupies the context
+/// for the cycles of `SoftmaxConstants::normalize`, so the cost grows with its length.
+#[context_macro]
+pub struct Softmax<E: Clone> {
+    input: Receiver<Array1<E>>,
+    output: Sender<Array1<E>>,
+    constants: SoftmaxConstants,
+}
+
+impl<E: DAMType + NdFloat> Softmax<E> {
+    pub fn new(
+        input: Receiver<Array1<E>>,
+        output: Sender<Array1<E>>,
+        constants: SoftmaxConstants,
+    ) -> Self {
+        let result = Self {
+            input,
+            output,
+            constants,
+            context_info: Default::default(),
+        };
+        result.input.attach_receiver(&result);
+        result.output.attach_sender(&result);
+        result
+    }
+}
+
+impl<E: DAMType + NdFloat> Context for Softmax<E> {
+    fn run(&mut self) {
+        loop {
+            match self.input.dequeue(&self.time) {
+                Ok(data) => {
+                    let (out, cycles) = self.constants.normalize(data.data.view());
+                    self.time.incr_cycles(cycles);
+                    self.output
+                        .enqueue(&self.time, ChannelElement::new(self.time.tick() + 1, out))
+                        .unwrap()
+                }
+                Err(_) => return,
+            }
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    path::Path,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use crate::{
+    gemm::Dataflow,
+    mesh::Hop,
+    perf::{self, Estimate, PerfParams},
+    trace,
+};
+
+/// Energy of every event in pJ, defaults are rough fp32 figures
+/// mac - One multiply accumulate
+/// sram_byte - Writing or reading a byte of a node's buffers
+/// link_byte - Moving a byte over a link or a bus tap
+/// elem_bytes - Bytes of an element in hardware
+#[derive(Copy, Clone, Debug)]
+pub struct EnergyModel {
+    pub mac: f64,
+    pub sram_byte: f64,
+    pub link_byte: f64,
+    pub elem_bytes: usize,
+}
+
+impl Default for EnergyModel {
+    fn default() -> Self {
+        Self {
+            mac: 4.6,
+            sram_byte: 1.25,
+            link_byte: 2.0,
+            elem_bytes: 4,
+        }
+    }
+}
+
+impl EnergyModel {
+    /// Energy of the MACs, the link traffic and the buffer accesses of `estimate`.
+    /// Every packet a node receives is written into a buffer and read back once.
+    pub fn energy(&self, params: &PerfParams, estimate: &Estimate) -> f64 {
+        let pkt_bytes = (params.link_capacity * self.elem_bytes) as f64;
+        let traffic = estimate.traffic;
+        let received = traffic.total() - traffic.outputs;
+        estimate.executed_macs as f64 * self.mac
+            + traffic.total() as f64 * pkt_bytes * self.link_byte
+            + 2.0 * received as f64 * pkt_bytes * self.sram_byte
+    }
+
+    /// SRAM of the whole mesh in bytes
+    pub fn sram_bytes(&self, params: &PerfParams, estimate: &Estimate) -> usize {
+        let nodes: usize = params.dims.iter().product();
+        nodes * estimate.buffer_elems * self.elem_bytes
+    }
+}
+
+/// Values of every swept parameter, a sweep runs all their combinations
+#[derive(Clone, Debug)]
+pub struct SweepSpace {
+    pub dims: Vec<[usize; 2]>,
+    pub link_capacity: Vec<usize>,
+    pub buffer_size: Vec<usize>,
+    pub initiation_interval: Vec<u64>,
+    pub dataflow: Vec<Dataflow>,
+}
+
+impl SweepSpace {
+    /// Every combination for a GEMM of `shape`, the last parameter changes fastest
+    pub fn points(&self, shape: [usize; 3]) -> Vec<PerfParams> {
+        let mut points = Vec::with_capacity(self.len());
+        for &dims in self.dims.iter() {
+            for &link_capacity in self.link_capacity.iter() {
+                for &buffer_size in self.buffer_size.iter() {
+                    for &ii in self.initiation_interval.iter() {
+                        for &dataflow in self.dataflow.iter() {
+                            let params = PerfParams::new(shape, dims, link_capacity, buffer_size)
+                                .with_initiation_interval(ii)
+                                .with_dataflow(dataflow);
+                            points.push(params);
+                        }
+                    }
+                }
+            }
+        }
+        points
+    }
+
+    pub fn len(&self) -> usize {
+        self.dims.len()
+            * self.link_capacity.len()
+            * self.buffer_size.len()
+            * self.initiation_interval.len()
+            * self.dataflow.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Outcome of one configuration
+/// estimated_cycles - Cycles of `perf::estimate`
+/// utilization - Mean useful MAC utilization of the nodes over the simulated cycles
+/// trace_bytes - Bytes of the traces left by the last pass
+/// energy_pj - Energy of `EnergyModel` over the packet and MAC counts of `perf::estimate`,
+/// not counted in the simulation. The counts don't depend on the hop, so with
+/// `Hop::Link` or `Hop::Credit` the serialization, wire latency and credit returns of the
+/// simulated links cost no energy.
+/// sram_bytes - SRAM of the whole mesh, from the buffer sizes of `perf::estimate`
+#[derive(Clone, Debug)]
+pub struct SweepResult {
+    pub params: PerfParams,
+    pub cycles: u64,
+    pub estimated_cycles: u64,
+    pub utilization: f64,
+    pub trace_bytes: u64,
+    pub energy_pj: f64,
+    pub sram_bytes: usize,
+}
+
+impl SweepResult {
+    /// Useful MACs per simulated cycle
+    pub fn macs_per_cycle(&self) -> f64 {
+        let macs: usize = self.params.shape.iter().product();
+        macs as f64 / self.cycles.max(1) as f64
+    }
+}
+
+/// Simulates every point of a `SweepSpace` for one GEMM shape, `jobs` points at a time.
+/// The traces of point `i`, header included, go to the `{name}/{i}` subdirectory of the
+/// trace dir, see `run_points` for the numbering of a subset of the points.
+pub struct Sweep {
+    name: String,
+    shape: [usize; 3],
+    space: SweepSpace,
+    hop: Hop,
+    jobs: usize,
+    energy: EnergyModel,
+}
+
+impl Sweep {
+    pub fn new(name: &str, shape: [usize; 3], space: SweepSpace) -> Self {
+        let jobs = thread::available_parallelism().map_or(1, |n| n.get());
+        Self {
+            name: name.to_string(),
+            shape,
+            space,
+            hop: Hop::Ideal,
+            jobs,
+            energy: EnergyModel::default(),
+        }
+    }
+
+    pub fn with_hop(mut self, hop: Hop) -> Self {
+        self.hop = hop;
+        self
+    }
+
+    /// Points simulated in parallel, the available parallelism by default
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        assert!(jobs > 0);
+        self.jobs = jobs;
+        self
+    }
+
+    pub fn with_energy(mut self, energy: EnergyModel) -> Self {
+        self.energy = energy;
+        self
+    }
+
+    pub fn energy(&self) -> EnergyModel {
+        self.energy
+    }
+
+    pub fn jobs(&self) -> usize {
+        self.jobs
+    }
+
+    /// Every point of the space, see `SweepSpace::points`
+    pub fn points(&self) -> Vec<PerfParams> {
+        self.space.points(self.shape)
+    }
+
+    /// Simulates `params` and checks the result, the traces go to the subdirectory `sub`
+    fn run_point(&self, params: &PerfParams, sub: &str) -> SweepResult {
+        trace::clean_trace_dir(sub);
+        let (x, w) = perf::operands(params.shape);
+        let reference = x.dot(&w);
+        let mapping = params.mapping(x, w).with_trace_prefix(&format!("{sub}/"));
+        let (y, cycles) = mapping.run(self.hop, params.dataflow);
+        assert_eq!(y, reference);
+        let estimate = perf::estimate(params);
+        SweepResult {
+            params: *params,
+            cycles,
+            estimated_cycles: estimate.cycles,
+            utilization: estimate.mean_utilization(cycles),
+            trace_bytes: trace::trace_size(sub),
+            energy_pj: self.energy.energy(params, &estimate),
+            sram_bytes: self.energy.sram_bytes(params, &estimate),
+        }
+    }
+
+    /// Results of `points` in their order. Point `i` of `points` is numbered
+    /// `first_index + i`, so that calls on successive slices don't share a subdirectory.
+    pub fn run_points(&self, points: &[PerfParams], first_index: usize) -> Vec<SweepResult> {
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(vec![None; points.len()]);
+        thread::scope(|s| {
+            for _ in 0..self.jobs.min(points.len()) {
+                s.spawn(|| {
+                    loop {
+                        let idx = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(params) = points.get(idx) else {
+                            break;
+                        };
+                        let sub = format!("{name}/{i}", name = self.name, i = first_index + idx);
+                        let result = self.run_point(params, &sub);
+                        results.lock().unwrap()[idx] = Some(result);
+                    }
+                });
+            }
+        });
+        Vec::from_iter(results.into_inner().unwrap().into_iter().flatten())
+    }
+
+    /// Results of every point of the space in the order of `SweepSpace::points`
+    pub fn run(&self) -> Vec<SweepResult> {
+        self.run_points(&self.points(), 0)
+    }
+}
+
+fn dataflow_name(dataflow: Dataflow) -> String {
+    match dataflow {
+        Dataflow::Systolic => "systolic".to_string(),
+        Dataflow::Multicast { bus_latency } => format!("multicast:{bus_latency}"),
+    }
+}
+
+const COLUMNS: [&str; 12] = [
+    "shape",
+    "dims",
+    "link_capacity",
+    "buffer_size",
+    "initiation_interval",
+    "dataflow",
+    "cycles",
+    "estimated_cycles",
+    "utilization",
+    "trace_bytes",
+    "energy_pj",
+    "sram_bytes",
+];
+
+/// Values of `COLUMNS`, strings are quoted for JSON
+fn row(result: &SweepResult, quote: bool) -> [String; 12] {
+    let p = &result.params;
+    let q = |s: String| if quote { format!("\"{s}\"") } else { s };
+    let [m, k, n] = p.shape;
+    [
+        q(format!("{m}x{k}x{n}")),
+        q(format!("{}x{}", p.dims[0], p.dims[1])),
+        p.link_capacity.to_string(),
+        p.buffer_size.to_string(),
+        p.initiation_interval.to_string(),
+        q(dataflow_name(p.dataflow)),
+        result.cycles.to_string(),
+        result.estimated_cycles.to_string(),
+        format!("{:.4}", result.utilization),
+        result.trace_bytes.to_string(),
+        format!("{:.1}", result.energy_pj),
+        result.sram_bytes.to_string(),
+    ]
+}
+
+pub fn to_csv(results: &[SweepResult]) -> String {
+    let mut csv = COLUMNS.join(",") + "\n";
+    for result in results {
+        csv += &(row(result, false).join(",") + "\n");
+    }
+    csv
+}
+
+pub fn to_json(results: &[SweepResult]) -> String {
+    let objects = Vec::from_iter(results.iter().map(|result| {
+        let fields = COLUMNS
+            .iter()
+            .zip(row(result, true))
+            .map(|(col, val)| format!("\"{col}\": {val}"));
+        format!("  {{{}}}", Vec::from_iter(fields).join(", "))
+    }));
+    format!("[\n{}\n]\n", objects.join(",\n"))
+}
+
+/// Writes `results` as JSON if `path` ends in `.json`, as CSV otherwise
+pub fn write_table(results: &[SweepResult], path: &Path) -> std::io::Result<()> {
+    let table = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => to_json(results),
+        _ => to_csv(results),
+    };
+    std::fs::write(path, table)
+}
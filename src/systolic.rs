@@ -0,0 +1,249 @@
+use dam::{context_tools::*, simulation::ProgramBuilder};
+use ndarray::{LinalgScalar, prelude::*};
+
+/// How a mesh node is simulated
+#[derive(Copy, Clone, Debug)]
+pub enum NodeModel {
+    /// One `Gemm` context computing a whole tile per matmul
+    Coarse,
+    /// One `Pe` context per weight, passing single elements to its neighbours.
+    /// fifo_depth - Elements buffered between two PEs
+    PeLevel { mac_latency: u64, fifo_depth: usize },
+}
+
+/// Multiply accumulate PE of a weight stationary array. Every cycle it takes an input
+/// from the left and a partial sum from above, forwards the input to the right and
+/// sends `psum + x * weight` down `mac_latency` cycles later.
+#[context_macro]
+pub struct Pe<E: Clone> {
+    weight: E,
+    x_in: Receiver<E>,
+    psum_in: Receiver<E>,
+    x_out: Sender<E>,
+    psum_out: Sender<E>,
+    mac_latency: u64,
+}
+
+impl<E: DAMType + LinalgScalar> Pe<E> {
+    pub fn new(
+        weight: E,
+        x_in: Receiver<E>,
+        psum_in: Receiver<E>,
+        x_out: Sender<E>,
+        psum_out: Sender<E>,
+        mac_latency: u64,
+    ) -> Self {
+        let result = Self {
+            weight,
+            x_in,
+            psum_in,
+            x_out,
+            psum_out,
+            mac_latency,
+            context_info: Default::default(),
+        };
+        result.x_in.attach_receiver(&result);
+        result.psum_in.attach_receiver(&result);
+        result.x_out.attach_sender(&result);
+        result.psum_out.attach_sender(&result);
+        result
+    }
+}
+
+impl<E: DAMType + LinalgScalar> Context for Pe<E> {
+    fn run(&mut self) {
+        loop {
+            let x = match self.x_in.dequeue(&self.time) {
+                Ok(data) => data.data,
+                Err(_) => return,
+            };
+            let psum = match self.psum_in.dequeue(&self.time) {
+                Ok(data) => data.data,
+                Err(_) => panic!("Partial sums ended before the inputs"),
+            };
+            let cur_time = self.time.tick();
+            let out = psum + x * self.weight;
+            self.x_out
+                .enqueue(&self.time, ChannelElement::new(cur_time + 1, x))
+                .unwrap();
+            self.psum_out
+                .enqueue(
+                    &self.time,
+                    ChannelElement::new(cur_time + self.mac_latency.max(1), out),
+                )
+                .unwrap();
+            self.time.incr_cycles(1);
+        }
+    }
+}
+
+/// Splits packets into element streams, element `i` of a packet goes to
+/// `outputs[i % outputs.len()]`. Sends one element to every output per cycle.
+#[context_macro]
+pub struct Unpack<E: Clone> {
+    input: Receiver<Array1<E>>,
+    outputs: Vec<Sender<E>>,
+}
+
+impl<E: DAMType> Unpack<E> {
+    pub fn new(input: Receiver<Array1<E>>, outputs: Vec<Sender<E>>) -> Self {
+        assert!(!outputs.is_empty());
+        let result = Self {
+            input,
+            outputs,
+            context_info: Default::default(),
+        };
+        result.input.attach_receiver(&result);
+        result.outputs.iter().for_each(|x| x.attach_sender(&result));
+        result
+    }
+}
+
+impl<E: DAMType> Context for Unpack<E> {
+    fn run(&mut self) {
+        let width = self.outputs.len();
+        loop {
+            let packet = match self.input.dequeue(&self.time) {
+                Ok(data) => data.data,
+                Err(_) => return,
+            };
+            assert!(packet.len().is_multiple_of(width));
+            for elems in packet.as_slice().unwrap().chunks(width) {
+                let cur_time = self.time.tick();
+                for (output, elem) in self.outputs.iter().zip(elems) {
+                    output
+                        .enqueue(&self.time, ChannelElement::new(cur_time + 1, elem.clone()))
+                        .unwrap();
+                }
+                self.time.incr_cycles(1);
+            }
+        }
+    }
+}
+
+/// Inverse of `Unpack`, gathers one element of every input per cycle into packets of
+/// `packet_len` elements
+#[context_macro]
+pub struct Pack<E: Clone> {
+    inputs: Vec<Receiver<E>>,
+    output: Sender<Array1<E>>,
+    packet_len: usize,
+}
+
+impl<E: DAMType> Pack<E> {
+    pub fn new(inputs: Vec<Receiver<E>>, output: Sender<Array1<E>>, packet_len: usize) -> Self {
+        assert!(packet_len.is_multiple_of(inputs.len()));
+        let result = Self {
+            inputs,
+            output,
+            packet_len,
+            context_info: Default::default(),
+        };
+        result
+            .inputs
+            .iter()
+            .for_each(|x| x.attach_receiver(&result));
+        result.output.attach_sender(&result);
+        result
+    }
+}
+
+impl<E: DAMType> Context for Pack<E> {
+    fn run(&mut self) {
+        loop {
+            let mut packet = Vec::with_capacity(self.packet_len);
+            while packet.len() < self.packet_len {
+                for input in self.inputs.iter() {
+                    match input.dequeue(&self.time) {
+                        Ok(data) => packet.push(data.data),
+                        Err(_) if packet.is_empty() => return,
+                        Err(_) => panic!("Input closed in the middle of a packet"),
+                    }
+                }
+                self.time.incr_cycles(1);
+            }
+            let ce = ChannelElement::new(self.time.tick() + 1, Array::from_vec(packet));
+            self.output.enqueue(&self.time, ce).unwrap();
+        }
+    }
+}
+
+/// (senders, receivers) of a `rows x cols` grid of element channels
+type ChanGrid<E> = (Vec<Vec<Option<Sender<E>>>>, Vec<Vec<Option<Receiver<E>>>>);
+
+fn grid<'a, E: DAMType>(
+    rows: usize,
+    cols: usize,
+    depth: usize,
+    ctx: &mut ProgramBuilder<'a>,
+) -> ChanGrid<E> {
+    let mut result: ChanGrid<E> = (Vec::with_capacity(rows), Vec::with_capacity(rows));
+    for _ in 0..rows {
+        let (tx, rx) = (0..cols)
+            .map(|_| {
+                let (tx, rx) = ctx.bounded::<E>(depth);
+                (Some(tx), Some(rx))
+            })
+            .unzip();
+        result.0.push(tx);
+        result.1.push(rx);
+    }
+    result
+}
+
+/// Adds the PE-level equivalent of a `Gemm` node holding `weights`, `[tk, tn]`, with the
+/// ports of `mesh_conn`. Input packets are unpacked into one element stream per PE row,
+/// partial sum packets into one stream per PE column, and both are packed again at the
+/// right and bottom edges of the `tk x tn` array. The inputs always leave to the right,
+/// they end in a consumer under multicast.
+pub fn pe_node<'a, E: DAMType + LinalgScalar>(
+    weights: Array2<E>,
+    link_capacity: usize,
+    input: [Receiver<Array1<E>>; 2],
+    output: [Sender<Array1<E>>; 2],
+    mac_latency: u64,
+    fifo_depth: usize,
+    ctx: &mut ProgramBuilder<'a>,
+) {
+    let (tk, tn) = weights.dim();
+    let [x_recv, psum_recv] = input;
+    let [x_send, psum_send] = output;
+    // x_*[k][n] feeds PE (k, n), column tn is the right edge
+    let (mut x_tx, mut x_rx) = grid(tk, tn + 1, fifo_depth, ctx);
+    // psum_*[k][n] feeds PE (k, n), row tk is the bottom edge
+    let (mut psum_tx, mut psum_rx) = grid(tk + 1, tn, fifo_depth, ctx);
+    let x_left = Vec::from_iter((0..tk).map(|k| x_tx[k][0].take().unwrap()));
+    let x_right = Vec::from_iter((0..tk).map(|k| x_rx[k][tn].take().unwrap()));
+    let psum_top = Vec::from_iter((0..tn).map(|n| psum_tx[0][n].take().unwrap()));
+    let psum_bottom = Vec::from_iter((0..tn).map(|n| psum_rx[tk][n].take().unwrap()));
+    for k in 0..tk {
+        for n in 0..tn {
+            ctx.add_child(Pe::new(
+                weights[[k, n]],
+                x_rx[k][n].take().unwrap(),
+                psum_rx[k][n].take().unwrap(),
+                x_tx[k][n + 1].take().unwrap(),
+                psum_tx[k + 1][n].take().unwrap(),
+                mac_latency,
+            ));
+        }
+    }
+    ctx.add_child(Unpack::new(x_recv, x_left));
+    ctx.add_child(Unpack::new(psum_recv, psum_top));
+    ctx.add_child(Pack::new(x_right, x_send, link_capacity));
+    ctx.add_child(Pack::new(psum_bottom, psum_send, link_capacity));
+}
+
+/// Cycles of one run under the coarse and the PE-level node models
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ModelComparison {
+    pub coarse: u64,
+    pub pe_level: u64,
+}
+
+impl ModelComparison {
+    /// Signed error of the coarse model relative to the PE-level ground truth
+    pub fn relative_error(&self) -> f64 {
+        (self.coarse as f64 - self.pe_level as f64) / self.pe_level.max(1) as f64
+    }
+}
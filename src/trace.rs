@@ -33,6 +33,24 @@ pub fn clean_trace() {
     std::fs::remove_dir_all(DIR).unwrap();
     std::fs::create_dir_all(DIR).unwrap();
 }
+/// Empties the subdirectory `sub` of the trace dir, traces named `{sub}/...` go there
+pub fn clean_trace_dir(sub: &str) {
+    let dir = format!("{DIR}/{sub}");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+}
+
+/// Bytes of the traces in the subdirectory `sub` of the trace dir
+pub fn trace_size(sub: &str) -> u64 {
+    let Ok(entries) = std::fs::read_dir(format!("{DIR}/{sub}")) else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| entry.ok()?.metadata().ok())
+        .map(|meta| meta.len())
+        .sum()
+}
+
 pub fn mk_trace_file(fname: &str) -> File {
     let fname = format!("{DIR}/{fname}", DIR = DIR, fname = fname);
     File::create(fname).unwrap()
@@ -99,6 +117,17 @@ pub fn get_trace_descriptors<const N: usize>(
     processes: Vec<(String, Vec<String>)>,
     desc_count: usize,
     thread_count: usize,
+) -> Vec<[u64; N]> {
+    get_prefixed_trace_descriptors(processes, desc_count, thread_count, "")
+}
+
+/// Same as `get_trace_descriptors`, the header goes to `{prefix}header_0_.perfetto` so
+/// that it sits next to traces written with the same prefix
+pub fn get_prefixed_trace_descriptors<const N: usize>(
+    processes: Vec<(String, Vec<String>)>,
+    desc_count: usize,
+    thread_count: usize,
+    prefix: &str,
 ) -> Vec<[u64; N]> {
     // let mut pid = 0;
     let mut tpkts = Vec::with_capacity(desc_count);
@@ -138,6 +167,6 @@ pub fn get_trace_descriptors<const N: usize>(
         }
         // pid += 1;
     }
-    write_trace("header_0_.perfetto", tpkts);
+    write_trace(format!("{prefix}header_0_.perfetto").as_str(), tpkts);
     tuuids
 }
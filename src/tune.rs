@@ -0,0 +1,108 @@
+use crate::{
+    perf,
+    sweep::{Sweep, SweepResult},
+};
+
+/// Points with the least SRAM for their cycles, by increasing SRAM and decreasing cycles.
+/// A point is dropped when another one needs no more SRAM and no more cycles.
+pub fn pareto_front(results: &[SweepResult]) -> Vec<SweepResult> {
+    let mut sorted = results.to_vec();
+    sorted.sort_by_key(|r| (r.sram_bytes, r.cycles));
+    let mut front: Vec<SweepResult> = Vec::new();
+    for result in sorted {
+        match front.last() {
+            Some(last) if last.cycles <= result.cycles => (),
+            _ => front.push(result),
+        }
+    }
+    front
+}
+
+/// Outcome of `Tuner::tune`
+/// best - Point with the least SRAM reaching the target, None if no point does
+/// evaluated - Simulated points, by increasing SRAM
+/// pruned - Points skipped because the analytical model already misses the target
+#[derive(Clone, Debug)]
+pub struct TuneReport {
+    pub best: Option<SweepResult>,
+    pub evaluated: Vec<SweepResult>,
+    pub pruned: usize,
+}
+
+/// Searches the points of a sweep, typically over `buffer_size` and `link_capacity`,
+/// for the least SRAM reaching `target_macs_per_cycle`. Points are simulated greedily
+/// from the smallest SRAM up, `jobs` at a time, until one reaches the target. Points
+/// whose `perf::estimate` misses the target are not simulated, slower hops only add
+/// cycles to the estimate. With `Hop::Ideal`, the default of `Sweep`, the estimate is
+/// exact and the simulations only confirm it, set a slower hop with `Sweep::with_hop`
+/// for them to change the outcome.
+pub struct Tuner {
+    sweep: Sweep,
+    target_macs_per_cycle: f64,
+}
+
+impl Tuner {
+    pub fn new(sweep: Sweep, target_macs_per_cycle: f64) -> Self {
+        assert!(target_macs_per_cycle > 0.0);
+        Self {
+            sweep,
+            target_macs_per_cycle,
+        }
+    }
+
+    fn meets_target(&self, macs: usize, cycles: u64) -> bool {
+        macs as f64 >= self.target_macs_per_cycle * cycles as f64
+    }
+
+    pub fn tune(&self) -> TuneReport {
+        let energy = self.sweep.energy();
+        let mut candidates = Vec::from_iter(self.sweep.points().into_iter().map(|params| {
+            let estimate = perf::estimate(&params);
+            (
+                energy.sram_bytes(&params, &estimate),
+                estimate.cycles,
+                params,
+            )
+        }));
+        candidates.sort_by_key(|(sram, cycles, p)| (*sram, *cycles, p.link_capacity));
+        let total = candidates.len();
+        let candidates = Vec::from_iter(candidates.into_iter().filter_map(|(_, cycles, p)| {
+            let macs: usize = p.shape.iter().product();
+            self.meets_target(macs, cycles).then_some(p)
+        }));
+        let pruned = total - candidates.len();
+
+        let mut evaluated = Vec::new();
+        let mut best = None;
+        for batch in candidates.chunks(self.sweep.jobs()) {
+            let results = self.sweep.run_points(batch, evaluated.len());
+            best = results
+                .iter()
+                .find(|r| self.meets_target(r.params.shape.iter().product(), r.cycles))
+                .cloned();
+            evaluated.extend(results);
+            if best.is_some() {
+                break;
+            }
+        }
+        TuneReport {
+            best,
+            evaluated,
+            pruned,
+        }
+    }
+
+    /// `pareto_front` of every point, only the points that `report` did not evaluate are
+    /// simulated
+    pub fn front(&self, report: &TuneReport) -> Vec<SweepResult> {
+        let rest = Vec::from_iter(
+            self.sweep
+                .points()
+                .into_iter()
+                .filter(|p| report.evaluated.iter().all(|r| r.params != *p)),
+        );
+        let mut results = report.evaluated.clone();
+        results.extend(self.sweep.run_points(&rest, report.evaluated.len()));
+        pareto_front(&results)
+    }
+}
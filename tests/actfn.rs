@@ -0,0 +1,145 @@
+use std::sync::{Arc, Mutex};
+
+use dam::{
+    simulation::{InitializationOptionsBuilder, ProgramBuilder, RunOptions},
+    utility_contexts::GeneratorContext,
+};
+use dgemm::{
+    actfn::{Actfn, Activation, Builtin, PacketActfn},
+    consumer::Collector,
+    mapper::Mapping,
+    mesh::MeshSink,
+    trace::clean_trace,
+};
+use ndarray::*;
+
+mod common;
+
+type Reference = fn(f64) -> f64;
+
+/// Runs `inputs` through an `Actfn` applying `func`, returns the outputs and the cycles
+fn run_actfn(inputs: Vec<f64>, func: Box<dyn Activation<f64>>) -> (Vec<f64>, u64) {
+    let mut ctx = ProgramBuilder::default();
+    let (x_send, x_recv) = ctx.bounded(4);
+    let (y_send, y_recv) = ctx.bounded(4);
+    ctx.add_child(GeneratorContext::new(|| inputs.into_iter(), x_send));
+    ctx.add_child(Actfn::from_activation(x_recv, y_send, 1, func));
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    ctx.add_child(Collector::new(y_recv, buffer.clone()));
+    let executed = ctx
+        .initialize(
+            InitializationOptionsBuilder::default()
+                .run_flavor_inference(true)
+                .build()
+                .unwrap(),
+        )
+        .unwrap()
+        .run(RunOptions::default());
+    let outputs = buffer.lock().unwrap().clone();
+    (outputs, executed.elapsed_cycles().unwrap())
+}
+
+#[test]
+fn actfn_closure_test() {
+    let inputs = vec![-2.0, -0.5, 0.0, 1.5, 3.0];
+    // Captured parameter
+    let slope = 0.1;
+    let (leaky, _) = run_actfn(
+        inputs.clone(),
+        Box::new(move |x: f64| if x < 0.0 { slope * x } else { x }),
+    );
+    assert_eq!(leaky, vec![-0.2, -0.05, 0.0, 1.5, 3.0]);
+    // Running state, a prefix sum
+    let mut total = 0.0;
+    let (prefix, _) = run_actfn(
+        inputs.clone(),
+        Box::new(move |x: f64| {
+            total += x;
+            total
+        }),
+    );
+    assert_eq!(prefix, vec![-2.0, -2.5, -2.5, -1.0, 2.0]);
+
+    let library: [(Builtin, Reference); 5] = [
+        (Builtin::Gelu, |x| {
+            0.5 * x
+                * (1.0 + ((2.0 / std::f64::consts::PI).sqrt() * (x + 0.044715 * x.powi(3))).tanh())
+        }),
+        (Builtin::Silu, |x| x / (1.0 + (-x).exp())),
+        (Builtin::Tanh, f64::tanh),
+        (Builtin::Sigmoid, |x| 1.0 / (1.0 + (-x).exp())),
+        (Builtin::HardSwish, |x| x * (x + 3.0).clamp(0.0, 6.0) / 6.0),
+    ];
+    for (builtin, expected) in library {
+        let (outputs, cycles) = run_actfn(inputs.clone(), Box::new(builtin));
+        println!("{:?}|Took {:?} cycles", builtin, cycles);
+        for (out, x) in outputs.iter().zip(inputs.iter()) {
+            assert!((out - expected(*x)).abs() < 1e-12);
+        }
+        // The last element leaves after the unit's latency
+        let latency = Activation::<f64>::latency(&builtin);
+        assert!(cycles >= inputs.len() as u64 - 1 + latency);
+    }
+}
+
+#[test]
+fn packet_actfn_mesh_test() {
+    const LINK_CAPACITY: usize = 4;
+    const FEATURES: usize = 4;
+    const BUFFER_CAPACITY: usize = 2;
+    const NUM_INPUTS: usize = 8;
+    const DIMS: [usize; 2] = [2, 2];
+    const LANES: usize = 1;
+    const II: u64 = 2;
+
+    clean_trace();
+    let x = Array::from_shape_fn([NUM_INPUTS, DIMS[0] * FEATURES], |(i, j)| {
+        ((i + j) % 5) as f64 - 1.0
+    });
+    let w = Array::from_shape_fn([DIMS[0] * FEATURES, DIMS[1] * FEATURES], |(i, j)| {
+        ((i * j) % 3) as f64 - 1.0
+    });
+    let ref_out = x.dot(&w).mapv(|v| Builtin::HardSwish.eval(v));
+
+    let mut ctx = ProgramBuilder::default();
+    let mapping = Mapping::new(x, w, DIMS, LINK_CAPACITY, BUFFER_CAPACITY);
+    let (in_prods, out_cons) = common::add_linear_mesh(&mapping, &mut ctx);
+    // One activation unit on every bottom port
+    let mut y_sends = Vec::new();
+    let mut buffers = Vec::new();
+    let mut packet_cycles = 0;
+    for _ in 0..DIMS[1] {
+        let (y_send, y_recv) = ctx.bounded(BUFFER_CAPACITY);
+        let (act_send, act_recv) = ctx.bounded(BUFFER_CAPACITY);
+        let act = PacketActfn::new(y_recv, act_send, LANES, II, Box::new(Builtin::HardSwish));
+        packet_cycles = act.packet_cycles(LINK_CAPACITY);
+        ctx.add_child(act);
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        ctx.add_child(Collector::new(act_recv, buffer.clone()));
+        y_sends.push(y_send);
+        buffers.push(buffer);
+    }
+    let harness = mapping.harness(0);
+    harness.attach(in_prods, out_cons, MeshSink::Forward(y_sends), &mut ctx);
+    let executed = ctx
+        .initialize(
+            InitializationOptionsBuilder::default()
+                .run_flavor_inference(true)
+                .build()
+                .unwrap(),
+        )
+        .unwrap()
+        .run(RunOptions::default());
+    println!("Took {:?} cycles", executed.elapsed_cycles());
+    for (stream, buffer) in harness.output_streams(&ref_out).iter().zip(buffers) {
+        let outputs = buffer.lock().unwrap();
+        assert_eq!(outputs.len(), stream.len());
+        for (out, expected) in outputs.iter().zip(stream) {
+            assert!((out - expected).iter().all(|e| e.abs() < 1e-12));
+        }
+    }
+    // A single lane is the bottleneck of every column
+    assert_eq!(packet_cycles, LINK_CAPACITY as u64 * II);
+    let packets = (NUM_INPUTS * FEATURES / LINK_CAPACITY) as u64;
+    assert!(executed.elapsed_cycles().unwrap() >= packets * packet_cycles);
+}
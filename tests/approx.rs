@@ -0,0 +1,62 @@
+use std::sync::{Arc, Mutex};
+
+use dam::{
+    simulation::{InitializationOptionsBuilder, ProgramBuilder, RunOptions},
+    utility_contexts::GeneratorContext,
+};
+use dgemm::{
+    actfn::{Actfn, Builtin},
+    approx::{ApproxReport, ApproxTable, TableActivation},
+    consumer::Collector,
+};
+
+/// Runs `inputs` through an `Actfn` evaluating `table`, returns the error report
+fn run_table(inputs: Vec<f64>, table: ApproxTable, exact: fn(f64) -> f64) -> ApproxReport {
+    let report = Arc::new(Mutex::new(ApproxReport::new(8, 0.01)));
+    let mut ctx = ProgramBuilder::default();
+    let (x_send, x_recv) = ctx.bounded(4);
+    let (y_send, y_recv) = ctx.bounded(4);
+    ctx.add_child(GeneratorContext::new(|| inputs.into_iter(), x_send));
+    let func = TableActivation::new(table, exact, report.clone());
+    ctx.add_child(Actfn::from_activation(x_recv, y_send, 1, Box::new(func)));
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    ctx.add_child(Collector::new(y_recv, buffer));
+    ctx.initialize(
+        InitializationOptionsBuilder::default()
+            .run_flavor_inference(true)
+            .build()
+            .unwrap(),
+    )
+    .unwrap()
+    .run(RunOptions::default());
+    report.lock().unwrap().clone()
+}
+
+#[test]
+fn approx_error_test() {
+    const NUM_INPUTS: usize = 200;
+    const ENTRIES: usize = 32;
+
+    let inputs = Vec::from_iter((0..NUM_INPUTS).map(|i| -4.0 + 8.0 * i as f64 / NUM_INPUTS as f64));
+    let functions: [fn(f64) -> f64; 2] = [|x| Builtin::Gelu.eval(x), |x| Builtin::Sigmoid.eval(x)];
+    for exact in functions {
+        let lut = ApproxTable::lut(exact, -4.0, 4.0, ENTRIES);
+        let pwl = ApproxTable::pwl(exact, -4.0, 4.0, ENTRIES);
+        assert_eq!(pwl.eval(0.0), exact(0.0));
+        let lut_report = run_table(inputs.clone(), lut, exact);
+        let pwl_report = run_table(inputs.clone(), pwl, exact);
+        println!("LUT:{:?}", lut_report);
+        println!("PWL:{:?}", pwl_report);
+        for report in [&lut_report, &pwl_report] {
+            assert_eq!(report.count, NUM_INPUTS);
+            assert_eq!(report.histogram.iter().sum::<usize>(), NUM_INPUTS);
+            assert!(report.mean_error().abs() <= report.mean_abs_error());
+            assert!(report.mean_abs_error() <= report.max_abs_error);
+        }
+        // Interpolation beats the step function of a table of the same size
+        assert!(pwl_report.max_abs_error < lut_report.max_abs_error);
+        assert!(pwl_report.max_abs_error < 0.01);
+        assert!(lut_report.max_abs_error < 0.2);
+        assert_eq!(pwl_report.histogram[0], NUM_INPUTS);
+    }
+}
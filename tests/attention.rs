@@ -0,0 +1,55 @@
+use dgemm::{
+    attention::Attention,
+    gemm::Dataflow,
+    mapper::Mapping,
+    mesh::Hop,
+    softmax::{SoftmaxConstants, SoftmaxMode},
+    trace::clean_trace,
+};
+use ndarray::*;
+
+#[test]
+fn attention_heads_test() {
+    const SEQ_LEN: usize = 6;
+    const NUM_HEADS: usize = 2;
+    const HEAD_DIM: usize = 4;
+    const DIMS: [usize; 2] = [2, 2];
+    const LINK_CAPACITY: usize = 4;
+    const BUFFER_CAPACITY: usize = 2;
+
+    clean_trace();
+    let width = NUM_HEADS * HEAD_DIM;
+    let q = Array::from_shape_fn([SEQ_LEN, width], |(i, j)| ((i * width + j) as f64).sin());
+    let k = Array::from_shape_fn([SEQ_LEN, width], |(i, j)| ((i + 3 * j) as f64).cos());
+    let v = Array::from_shape_fn([SEQ_LEN, width], |(i, j)| ((i + j) % 5) as f64 - 2.0);
+    let constants = SoftmaxConstants::new(SoftmaxMode::TwoPass, 1, 1);
+    let attention = Attention::new(NUM_HEADS, HEAD_DIM, DIMS, LINK_CAPACITY, BUFFER_CAPACITY)
+        .with_softmax(constants);
+    let expected = attention.reference(&q, &k, &v);
+    let (y, report) = attention.run(&q, &k, &v, Hop::Ideal, Dataflow::Systolic);
+    println!("{:?}|Took {:?} cycles", report, report.total());
+    assert_eq!(y.shape(), [SEQ_LEN, width]);
+    // The mesh sums in a different order than ndarray
+    assert!((&y - &expected).iter().all(|e| e.abs() < 1e-9));
+
+    // Each stage on its own, one after the other
+    let scale = (HEAD_DIM as f64).sqrt().recip();
+    for (h, cycles) in report.head_cycles.iter().enumerate() {
+        let cols = s![.., h * HEAD_DIM..(h + 1) * HEAD_DIM];
+        let kt = k.slice(cols).t().to_owned() * scale;
+        let mapping = |x: Array2<f64>, w| Mapping::new(x, w, DIMS, LINK_CAPACITY, BUFFER_CAPACITY);
+        let (mut probs, qk) =
+            mapping(q.slice(cols).to_owned(), kt).run(Hop::Ideal, Dataflow::Systolic);
+        let mut softmax = 0;
+        for mut row in probs.rows_mut() {
+            let (out, row_cycles) = constants.normalize(row.view());
+            row.assign(&out);
+            softmax += row_cycles;
+        }
+        let (_, pv) = mapping(probs, v.slice(cols).to_owned()).run(Hop::Ideal, Dataflow::Systolic);
+        println!("Head {h}|QK:{qk}|Softmax:{softmax}|PV:{pv}|Pipelined:{cycles}");
+        // The softmax starts on the first rows while QKᵀ still computes the rest
+        assert!(*cycles >= qk.max(softmax).max(pv));
+        assert!(*cycles < qk + softmax + pv);
+    }
+}
@@ -0,0 +1,125 @@
+//! Fixtures shared by the integration tests, each test binary uses part of them
+#![allow(dead_code)]
+
+use std::sync::{Arc, Mutex};
+
+use dam::{
+    context_tools::{Receiver, Sender},
+    simulation::{InitializationOptionsBuilder, ProgramBuilder, RunOptions},
+};
+use dgemm::{
+    gemm::{Dataflow, Tracks},
+    mapper::{Mapping, MeshBoundary},
+    memory::{MemReader, MemRequest, MemResponse, MemWriter},
+    mesh::{Hop, MeshSink, take_left_inputs},
+};
+use ndarray::*;
+use strum::EnumCount;
+
+pub const LINK_CAPACITY: usize = 4;
+pub const BUFFER_CAPACITY: usize = 2;
+
+/// Adds the `Gemm` nodes of `mapping`, which has to fit the mesh in one pass, with ideal
+/// systolic hops. Node `n` traces as thread `xpu{n}`. Returns the boundary ports.
+pub fn add_linear_mesh(mapping: &Mapping<f64>, ctx: &mut ProgramBuilder<'_>) -> MeshBoundary<f64> {
+    assert!(mapping.num_passes() == 1);
+    let num_nodes: usize = mapping.dims().iter().product();
+    let thread_names = Vec::from_iter((0..num_nodes).map(|n| format!("xpu{n}")));
+    let processes = vec![("xpu".to_string(), thread_names)];
+    let tuuids = dgemm::trace::get_trace_descriptors::<{ Tracks::COUNT }>(
+        processes,
+        num_nodes + 1,
+        num_nodes,
+    );
+    mapping.add_nodes(0, 0, Hop::Ideal, Dataflow::Systolic, &tuuids, 0, ctx)
+}
+
+/// Request and response channels of the ports of a memory
+pub type MemPorts = (
+    Vec<Receiver<MemRequest<f64>>>,
+    Vec<Sender<MemResponse<f64>>>,
+);
+
+/// Linear layer on a `dims` mesh with its activations read from and its outputs written
+/// back to one memory, laid out as the input stream of every mesh row followed by the
+/// output stream of every column. `memory` adds the context serving the ports. Checks
+/// the outputs against ndarray and returns the cycles.
+pub fn stored_linear(
+    x: Array2<f64>,
+    w: Array2<f64>,
+    dims: [usize; 2],
+    outstanding: usize,
+    memory: impl FnOnce(Arc<Mutex<Array1<f64>>>, MemPorts, &mut ProgramBuilder<'_>),
+) -> Option<u64> {
+    let (link_cap, buffer_size) = (LINK_CAPACITY, BUFFER_CAPACITY);
+    let ref_out = x.dot(&w);
+    let mapping = Mapping::new(x, w, dims, link_cap, buffer_size);
+    let harness = mapping.harness(0);
+    let x_streams = harness.input_streams();
+    let y_len = harness.output_streams(&ref_out)[0].len() * link_cap;
+    let x_len = x_streams[0].len() * link_cap;
+    let y_base = x_streams.len() * x_len;
+    let mut contents = Array1::zeros(y_base + dims[1] * y_len);
+    for (row, stream) in x_streams.iter().enumerate() {
+        let data = Array::from_iter(stream.iter().flat_map(|x| x.iter().cloned()));
+        contents
+            .slice_mut(s![row * x_len..(row + 1) * x_len])
+            .assign(&data);
+    }
+    let contents = Arc::new(Mutex::new(contents));
+
+    let mut ctx = ProgramBuilder::default();
+    let (mut in_prods, out_cons) = add_linear_mesh(&mapping, &mut ctx);
+    let (mut requests, mut responses) = (Vec::new(), Vec::new());
+    for (row, x_send) in take_left_inputs(&mut in_prods).into_iter().enumerate() {
+        let (req_send, req_recv) = ctx.bounded(outstanding);
+        let (resp_send, resp_recv) = ctx.bounded(outstanding);
+        let packets = Vec::from_iter(
+            (0..x_len / link_cap).map(|step| (row * x_len + step * link_cap, link_cap)),
+        );
+        ctx.add_child(MemReader::new(
+            packets,
+            outstanding,
+            req_send,
+            resp_recv,
+            x_send,
+        ));
+        requests.push(req_recv);
+        responses.push(resp_send);
+    }
+    let mut y_sends = Vec::new();
+    for col in 0..dims[1] {
+        let (y_send, y_recv) = ctx.bounded(buffer_size);
+        let (req_send, req_recv) = ctx.bounded(outstanding);
+        let (resp_send, resp_recv) = ctx.bounded(outstanding);
+        ctx.add_child(MemWriter::new(
+            y_base + col * y_len,
+            outstanding,
+            y_recv,
+            req_send,
+            resp_recv,
+        ));
+        y_sends.push(y_send);
+        requests.push(req_recv);
+        responses.push(resp_send);
+    }
+    harness.attach(in_prods, out_cons, MeshSink::Forward(y_sends), &mut ctx);
+    memory(contents.clone(), (requests, responses), &mut ctx);
+
+    let executed = ctx
+        .initialize(
+            InitializationOptionsBuilder::default()
+                .run_flavor_inference(true)
+                .build()
+                .unwrap(),
+        )
+        .unwrap()
+        .run(RunOptions::default());
+    let contents = contents.lock().unwrap();
+    for (col, stream) in harness.output_streams(&ref_out).iter().enumerate() {
+        let expected = Array::from_iter(stream.iter().flat_map(|x| x.iter().cloned()));
+        let base = y_base + col * y_len;
+        assert_eq!(contents.slice(s![base..base + y_len]), expected);
+    }
+    executed.elapsed_cycles()
+}
@@ -0,0 +1,85 @@
+use dgemm::{
+    conv::{Conv2d, Conv2dParams, Im2colMode},
+    gemm::Dataflow,
+    mapper::Mapping,
+    mesh::Hop,
+    trace::clean_trace,
+};
+use ndarray::*;
+
+#[test]
+fn conv_im2col_test() {
+    const DIMS: [usize; 2] = [2, 2];
+    const LINK_CAPACITY: usize = 4;
+    const BUFFER_CAPACITY: usize = 2;
+
+    clean_trace();
+    let image = Array::from_shape_fn([2, 3, 7, 6], |(b, c, y, x)| {
+        ((b + 2 * c + 3 * y + x) % 5) as f64 - 2.0
+    });
+    let weights = Array::from_shape_fn([9, 3, 3, 3], |(o, c, y, x)| {
+        ((o + c + 2 * y + x) % 3) as f64 - 1.0
+    });
+    let strided = Conv2dParams::new([3, 3])
+        .with_stride([2, 1])
+        .with_padding([1, 1]);
+    let dilated = Conv2dParams::new([3, 3])
+        .with_padding([2, 1])
+        .with_dilation([2, 1]);
+    assert_eq!(strided.output_size([7, 6]), [4, 6]);
+    assert_eq!(dilated.output_size([7, 6]), [7, 6]);
+    for params in [strided, dilated] {
+        let conv = Conv2d::new(
+            weights.clone(),
+            params,
+            DIMS,
+            LINK_CAPACITY,
+            BUFFER_CAPACITY,
+        )
+        .with_epilogue(
+            Array::from_vec(vec![1.0, 0.0, -1.0, 2.0, 0.0, 1.0, -2.0, 0.0, 1.0]),
+            |x| x.max(0.0),
+        );
+        let expected = conv.reference(&image);
+        let x = params.im2col(&image);
+        let (pre, pre_cycles, pre_traffic) = conv.run(
+            &image,
+            Im2colMode::Precomputed,
+            Hop::Ideal,
+            Dataflow::Systolic,
+        );
+        let (fly, fly_cycles, fly_traffic) =
+            conv.run(&image, Im2colMode::OnTheFly, Hop::Ideal, Dataflow::Systolic);
+        println!("Precomputed:{:?}|{:?}", pre_cycles, pre_traffic);
+        println!("On the fly:{:?}|{:?}", fly_cycles, fly_traffic);
+        assert_eq!(pre, expected);
+        assert_eq!(fly, expected);
+        // Every pass streams its columns of all rows again, N passes re-read the same X
+        let mapping = Mapping::streamed(
+            x.nrows(),
+            conv.weight_matrix(),
+            DIMS,
+            LINK_CAPACITY,
+            BUFFER_CAPACITY,
+        );
+        let tiling = mapping.tiling();
+        assert!(tiling.n_passes > 1);
+        let sent = mapping.num_passes() * mapping.num_inputs() * DIMS[0] * tiling.tk;
+        assert_eq!(pre_traffic.sent, sent);
+        assert_eq!(fly_traffic.sent, sent);
+        assert_eq!(pre_traffic.im2col, x.len());
+        assert_eq!(pre_traffic.input, tiling.n_passes * x.len());
+        // The image is read once per tap that is not padding
+        let taps = Array::from_shape_fn(x.dim(), |(m, k)| params.source(image.shape(), m, k))
+            .iter()
+            .filter(|idx| idx.is_some())
+            .count();
+        assert_eq!(fly_traffic.input, tiling.n_passes * taps);
+        // Overlapping 3x3 windows repeat every pixel several times
+        assert!(pre_traffic.duplication() > 1.0);
+        assert!(taps > image.len());
+        assert!(fly_traffic.input < pre_traffic.input);
+        // Gathering a tap per cycle is slower than streaming lowered packets
+        assert!(fly_cycles > pre_cycles);
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::{Arc, Mutex};
+
+use dam::{
+    simulation::{InitializationOptionsBuilder, ProgramBuilder, RunOptions},
+    utility_contexts::CheckerContext,
+};
+use dgemm::{
+    credit::{CreditConfig, CreditStats, CreditTracks, credit_link},
+    producer::Producer,
+    trace::clean_trace,
+};
+use strum::EnumCount;
+
+fn run_credit_link(
+    config: CreditConfig,
+    num_pkts: usize,
+    track_ids: [u64; CreditTracks::COUNT],
+) -> (u64, CreditStats) {
+    let mut ctx = ProgramBuilder::default();
+    let (in_send, in_recv) = ctx.bounded::<f64>(num_pkts);
+    let (out_send, out_recv) = ctx.bounded::<f64>(num_pkts);
+    let pkts = Vec::from_iter((0..num_pkts).map(|x| x as f64));
+    let expected = pkts.clone();
+    let stats = Arc::new(Mutex::new(CreditStats::default()));
+    ctx.add_child(Producer::new(|| pkts.into_iter(), in_send, 0, 0));
+    credit_link(
+        in_recv,
+        out_send,
+        config,
+        config.credits() as u32,
+        Some(track_ids),
+        stats.clone(),
+        &mut ctx,
+    );
+    ctx.add_child(CheckerContext::new(|| expected.into_iter(), out_recv));
+    let executed = ctx
+        .initialize(
+            InitializationOptionsBuilder::default()
+                .run_flavor_inference(true)
+                .build()
+                .unwrap(),
+        )
+        .unwrap()
+        .run(RunOptions::default());
+    let stats = *stats.lock().unwrap();
+    (executed.elapsed_cycles().unwrap(), stats)
+}
+
+#[test]
+fn credit_round_trip_test() {
+    const NUM_PKTS: usize = 64;
+    const LATENCY: u64 = 4;
+    const CREDIT_LATENCY: u64 = 3;
+    clean_trace();
+    let thread_names = vec!["starved".to_string(), "matched".to_string()];
+    let processes = vec![("credit".to_string(), thread_names)];
+    let tuuids = dgemm::trace::get_trace_descriptors::<{ CreditTracks::COUNT }>(processes, 3, 2);
+    // Round trip is 8 cycles, 2 credits allow a quarter of the link bandwidth
+    let starved = CreditConfig::new(2, LATENCY, CREDIT_LATENCY);
+    let matched = CreditConfig::new(starved.round_trip() as usize, LATENCY, CREDIT_LATENCY);
+    assert!(starved.max_throughput() == 0.25);
+    assert!(matched.max_throughput() == 1.0);
+    let (starved_cycles, starved_stats) = run_credit_link(starved, NUM_PKTS, tuuids[0]);
+    let (matched_cycles, matched_stats) = run_credit_link(matched, NUM_PKTS, tuuids[1]);
+    println!(
+        "Starved:{:?} cycles|{:?}|Matched:{:?} cycles|{:?}",
+        starved_cycles, starved_stats, matched_cycles, matched_stats
+    );
+    assert!(starved_cycles as f64 >= NUM_PKTS as f64 / starved.max_throughput());
+    assert!(matched_cycles < starved_cycles);
+    assert!(starved_stats.packets == NUM_PKTS && matched_stats.packets == NUM_PKTS);
+    // Every cycle beyond one per packet is spent waiting on credits
+    assert!(starved_stats.starved_cycles > 0);
+    assert!(starved_stats.starved_cycles + NUM_PKTS as u64 <= starved_cycles);
+    assert!(matched_stats.starved_cycles == 0);
+}
@@ -0,0 +1,130 @@
+use std::sync::{Arc, Mutex};
+
+use dam::simulation::{InitializationOptionsBuilder, ProgramBuilder, RunOptions};
+use dgemm::{
+    dma::{Dma, DmaConstants, DmaDescriptor, DmaTracks},
+    mapper::Mapping,
+    memory::{DramConfig, MemStats, Memory},
+    mesh::{MeshHarness, MeshSink, take_left_inputs},
+    trace::{clean_trace, clean_trace_dir, get_prefixed_trace_descriptors},
+};
+use ndarray::*;
+use strum::EnumCount;
+
+mod common;
+use common::{BUFFER_CAPACITY, LINK_CAPACITY};
+
+#[test]
+fn dma_linear_test() {
+    const FEATURES: usize = 2;
+    const OUTSTANDING: usize = 4;
+    const NUM_MATMULS: usize = 3;
+    const NUM_INPUTS: usize = NUM_MATMULS * BUFFER_CAPACITY * (LINK_CAPACITY / FEATURES);
+    const DIMS: [usize; 2] = [2, 2];
+    const K: usize = DIMS[0] * FEATURES;
+    const N: usize = DIMS[1] * FEATURES;
+
+    clean_trace();
+    clean_trace_dir("dma");
+    let num_dmas = DIMS[0] + DIMS[1];
+    let thread_names = Vec::from_iter((0..num_dmas).map(|n| format!("dma{n}")));
+    let processes = vec![("dma".to_string(), thread_names)];
+    let tuuids = get_prefixed_trace_descriptors::<{ DmaTracks::COUNT }>(
+        processes,
+        num_dmas + 1,
+        num_dmas,
+        "dma/",
+    );
+    // X: [M, K] and Y: [M, N] are stored row major, Y right after X
+    let x = Array::from_shape_fn([NUM_INPUTS, K], |(i, j)| ((i + 3 * j) % 7) as f64);
+    let w = Array::from_shape_fn([K, N], |(i, j)| ((i + j) % 3) as f64 - 1.0);
+    let ref_out = x.dot(&w);
+    let y_base = x.len();
+    let mut contents = Array1::zeros(y_base + ref_out.len());
+    contents
+        .slice_mut(s![..y_base])
+        .assign(&Array::from_iter(x.iter().cloned()));
+    let contents = Arc::new(Mutex::new(contents));
+    let stats = Arc::new(Mutex::new(MemStats::default()));
+
+    let mut ctx = ProgramBuilder::default();
+    // The engines read X, so the mesh is mapped without it
+    let mapping = Mapping::streamed(NUM_INPUTS, w, DIMS, LINK_CAPACITY, BUFFER_CAPACITY);
+    assert_eq!(
+        (mapping.tiling().tk, mapping.tiling().tn),
+        (FEATURES, FEATURES)
+    );
+    assert_eq!(mapping.num_matmuls(), NUM_MATMULS);
+    let (mut in_prods, out_cons) = common::add_linear_mesh(&mapping, &mut ctx);
+    // One engine per boundary stream, each on its own memory port
+    let (mut requests, mut responses) = (Vec::new(), Vec::new());
+    let mut add_dma = |dma_id: usize,
+                       desc: DmaDescriptor,
+                       outputs: Vec<_>,
+                       inputs: Vec<_>,
+                       ctx: &mut ProgramBuilder| {
+        let (req_send, req_recv) = ctx.bounded(OUTSTANDING);
+        let (resp_send, resp_recv) = ctx.bounded(OUTSTANDING);
+        let constants = DmaConstants::new(
+            LINK_CAPACITY,
+            OUTSTANDING,
+            dma_id as u32,
+            Some(tuuids[dma_id]),
+        )
+        .with_trace_prefix("dma/");
+        ctx.add_child(Dma::new(
+            vec![desc],
+            constants,
+            req_send,
+            resp_recv,
+            outputs,
+            inputs,
+        ));
+        requests.push(req_recv);
+        responses.push(resp_send);
+    };
+    // Row r reads the column block X[:, r * FEATURES..(r + 1) * FEATURES]
+    for (row, x_send) in take_left_inputs(&mut in_prods).into_iter().enumerate() {
+        let desc = DmaDescriptor::load(row * FEATURES, vec![NUM_INPUTS, FEATURES], vec![K, 1], 0);
+        add_dma(row, desc, vec![x_send], vec![], &mut ctx);
+    }
+    let mut y_sends = Vec::new();
+    for col in 0..DIMS[1] {
+        let (y_send, y_recv) = ctx.bounded(BUFFER_CAPACITY);
+        y_sends.push(y_send);
+        let base = y_base + col * FEATURES;
+        let desc = DmaDescriptor::store(base, vec![NUM_INPUTS, FEATURES], vec![N, 1], 0);
+        add_dma(DIMS[0] + col, desc, vec![], vec![y_recv], &mut ctx);
+    }
+    let harness = MeshHarness::chained(DIMS, LINK_CAPACITY, NUM_INPUTS, FEATURES, FEATURES);
+    harness.attach(in_prods, out_cons, MeshSink::Forward(y_sends), &mut ctx);
+    ctx.add_child(Memory::new(
+        contents.clone(),
+        DramConfig::new(2, 4, 1024, 16),
+        requests,
+        responses,
+        stats.clone(),
+    ));
+
+    let executed = ctx
+        .initialize(
+            InitializationOptionsBuilder::default()
+                .run_flavor_inference(true)
+                .build()
+                .unwrap(),
+        )
+        .unwrap()
+        .run(RunOptions::default());
+    println!(
+        "Took {:?} cycles|{:?}",
+        executed.elapsed_cycles(),
+        stats.lock().unwrap()
+    );
+    let y = contents.lock().unwrap().slice(s![y_base..]).to_owned();
+    assert_eq!(y.into_shape([NUM_INPUTS, N]).unwrap(), ref_out);
+    // A packet holds LINK_CAPACITY / FEATURES rows, one request per row
+    let stats = *stats.lock().unwrap();
+    assert_eq!(stats.reads, NUM_INPUTS * DIMS[0]);
+    assert_eq!(stats.writes, NUM_INPUTS * DIMS[1]);
+    assert!(std::path::Path::new("artifacts/trace/dma/dma_0_.perfetto").exists());
+}
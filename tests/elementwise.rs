@@ -0,0 +1,109 @@
+use std::sync::{Arc, Mutex};
+
+use dam::{
+    simulation::{InitializationOptionsBuilder, ProgramBuilder, RunOptions},
+    utility_contexts::GeneratorContext,
+};
+use dgemm::{
+    consumer::Collector,
+    elementwise::{Elementwise, ElementwiseOp, SyncStats},
+    mapper::Mapping,
+    mesh::MeshSink,
+    producer::Producer,
+    trace::clean_trace,
+};
+use ndarray::*;
+
+mod common;
+
+#[test]
+fn elementwise_residual_test() {
+    const LINK_CAPACITY: usize = 4;
+    const FEATURES: usize = 4;
+    const BUFFER_CAPACITY: usize = 2;
+    const NUM_INPUTS: usize = 8;
+    const DIMS: [usize; 2] = [2, 2];
+
+    clean_trace();
+    // Y = X W + X, the residual of mesh column c is the input of mesh row c
+    let width = DIMS[0] * FEATURES;
+    let x = Array::from_shape_fn([NUM_INPUTS, width], |(i, j)| ((i + 2 * j) % 5) as f64);
+    let w = Array::from_shape_fn([width, width], |(i, j)| ((i * j) % 3) as f64 - 1.0);
+    let ref_out = x.dot(&w) + &x;
+
+    let mut ctx = ProgramBuilder::default();
+    let mapping = Mapping::new(x.clone(), w, DIMS, LINK_CAPACITY, BUFFER_CAPACITY);
+    let (in_prods, out_cons) = common::add_linear_mesh(&mapping, &mut ctx);
+    let harness = mapping.harness(0);
+    let stats = Arc::new(Mutex::new(SyncStats::default()));
+    let mut y_sends = Vec::new();
+    let mut buffers = Vec::new();
+    for (col, stream) in harness.input_streams().into_iter().enumerate() {
+        let (y_send, y_recv) = ctx.bounded(BUFFER_CAPACITY);
+        let (res_send, res_recv) = ctx.bounded(BUFFER_CAPACITY);
+        let (out_send, out_recv) = ctx.bounded(BUFFER_CAPACITY);
+        ctx.add_child(Producer::new(|| stream.into_iter(), res_send, col, 0));
+        ctx.add_child(Elementwise::new(
+            y_recv,
+            res_recv,
+            out_send,
+            ElementwiseOp::Add,
+            1,
+            stats.clone(),
+        ));
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        ctx.add_child(Collector::new(out_recv, buffer.clone()));
+        y_sends.push(y_send);
+        buffers.push(buffer);
+    }
+    harness.attach(in_prods, out_cons, MeshSink::Forward(y_sends), &mut ctx);
+
+    // Gating with one packet too many on the left
+    let gate_stats = Arc::new(Mutex::new(SyncStats::default()));
+    let (lhs_send, lhs_recv) = ctx.bounded(BUFFER_CAPACITY);
+    let (rhs_send, rhs_recv) = ctx.bounded(BUFFER_CAPACITY);
+    let (gate_send, gate_recv) = ctx.bounded(BUFFER_CAPACITY);
+    let lhs = Vec::from_iter((0..4).map(|i| Array::from_elem(LINK_CAPACITY, i as f64 - 1.0)));
+    let rhs = Vec::from_iter((0..3).map(|_| Array::from_elem(LINK_CAPACITY, 2.0)));
+    ctx.add_child(GeneratorContext::new(|| lhs.into_iter(), lhs_send));
+    ctx.add_child(GeneratorContext::new(|| rhs.into_iter(), rhs_send));
+    ctx.add_child(Elementwise::new(
+        lhs_recv,
+        rhs_recv,
+        gate_send,
+        ElementwiseOp::Custom(|a, b| a.max(0.0) * b),
+        1,
+        gate_stats.clone(),
+    ));
+    let gated = Arc::new(Mutex::new(Vec::new()));
+    ctx.add_child(Collector::new(gate_recv, gated.clone()));
+
+    let executed = ctx
+        .initialize(
+            InitializationOptionsBuilder::default()
+                .run_flavor_inference(true)
+                .build()
+                .unwrap(),
+        )
+        .unwrap()
+        .run(RunOptions::default());
+    let stats = *stats.lock().unwrap();
+    let gate_stats = *gate_stats.lock().unwrap();
+    println!(
+        "Took {:?} cycles|{:?}|{:?}",
+        executed.elapsed_cycles(),
+        stats,
+        gate_stats
+    );
+    for (stream, buffer) in harness.output_streams(&ref_out).iter().zip(buffers) {
+        assert_eq!(stream, &*buffer.lock().unwrap());
+    }
+    assert!(stats.is_aligned());
+    assert_eq!(stats.pairs, DIMS[1] * NUM_INPUTS * FEATURES / LINK_CAPACITY);
+    // The residual is ready long before the mesh output
+    assert!(stats.skewed > 0 && stats.max_skew > 0);
+    assert_eq!(gate_stats.unmatched, [1, 0]);
+    let gated = gated.lock().unwrap();
+    assert_eq!(gated.len(), 3);
+    assert_eq!(gated[2], Array::from_elem(LINK_CAPACITY, 2.0));
+}
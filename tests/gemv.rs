@@ -0,0 +1,63 @@
+use dam::{
+    simulation::{InitializationOptionsBuilder, ProgramBuilder, RunOptions},
+    utility_contexts::{CheckerContext, GeneratorContext},
+};
+use dgemm::{
+    actfn::Actfn,
+    gemv::{GEMV, GemvTracks},
+    trace::clean_trace,
+};
+use ndarray::*;
+use strum::EnumCount;
+
+#[test]
+fn gemv_batch_trace_test() {
+    const NUM_INPUTS: usize = 10;
+    const NUM_FEATURES: usize = 16;
+    const NUM_OUTPUTS: usize = 8;
+    const BATCH: usize = 4;
+    const LANES: usize = 32;
+
+    clean_trace();
+    let processes = vec![("gemv".to_string(), vec!["gemv0".to_string()])];
+    let tuuids = dgemm::trace::get_trace_descriptors::<{ GemvTracks::COUNT }>(processes, 2, 1);
+    let x = Array::from_shape_fn([NUM_INPUTS, NUM_FEATURES], |(i, j)| ((i + j) % 7) as f64);
+    let weights = Array::from_shape_fn([NUM_OUTPUTS, NUM_FEATURES], |(i, j)| {
+        ((i * j) % 3) as f64 - 1.0
+    });
+    let biases = Array::from_shape_fn(NUM_OUTPUTS, |i| i as f64 - 4.0);
+    let ref_out = (x.dot(&weights.t()) + &biases).mapv(|v| v.max(0.0));
+
+    let mut ctx = ProgramBuilder::default();
+    let (x_send, x_recv) = ctx.bounded::<f64>(NUM_FEATURES);
+    let (mm_send, mm_recv) = ctx.bounded::<f64>(NUM_FEATURES);
+    let (act_send, act_recv) = ctx.bounded::<f64>(NUM_FEATURES);
+    let inputs = Vec::from_iter(x.iter().cloned());
+    ctx.add_child(GeneratorContext::new(|| inputs.into_iter(), x_send));
+    let gemv = GEMV::new(x_recv, mm_send, weights, biases, 1)
+        .with_batch(BATCH)
+        .with_lanes(LANES)
+        .with_trace(0, tuuids[0]);
+    // Two full batches and a half one
+    let compute_cycles = 2 * gemv.compute_cycles(BATCH) + gemv.compute_cycles(NUM_INPUTS % BATCH);
+    assert_eq!(
+        compute_cycles,
+        (NUM_INPUTS * NUM_OUTPUTS * NUM_FEATURES / LANES) as u64
+    );
+    ctx.add_child(gemv);
+    ctx.add_child(Actfn::new(mm_recv, act_send, 1, |v: f64| v.max(0.0)));
+    ctx.add_child(CheckerContext::new(move || ref_out.into_iter(), act_recv));
+    let executed = ctx
+        .initialize(
+            InitializationOptionsBuilder::default()
+                .run_flavor_inference(true)
+                .build()
+                .unwrap(),
+        )
+        .unwrap()
+        .run(RunOptions::default());
+    println!("Took {:?} cycles", executed.elapsed_cycles());
+    let read_cycles = (NUM_INPUTS * NUM_FEATURES) as u64;
+    assert!(executed.elapsed_cycles().unwrap() >= read_cycles + compute_cycles);
+    assert!(std::path::Path::new("artifacts/trace/gemv_0_.perfetto").exists());
+}
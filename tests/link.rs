@@ -0,0 +1,53 @@
+use dam::{
+    simulation::{InitializationOptionsBuilder, ProgramBuilder, RunOptions},
+    utility_contexts::CheckerContext,
+};
+use dgemm::{
+    link::{Link, LinkConfig},
+    producer::Producer,
+};
+use ndarray::prelude::*;
+
+fn run_link(config: LinkConfig, link_capacity: usize, num_pkts: usize) -> u64 {
+    let mut ctx = ProgramBuilder::default();
+    let (tx, link_rx) = ctx.bounded::<Array1<f64>>(2);
+    let (link_tx, rx) = ctx.bounded::<Array1<f64>>(2);
+    let pkts = Vec::from_iter(
+        (0..num_pkts).map(|i| Array::range(0., link_capacity as f64, 1.) + (i as f64)),
+    );
+    let expected = pkts.clone();
+    ctx.add_child(Producer::new(|| pkts.into_iter(), tx, 0, 0));
+    ctx.add_child(Link::new(link_rx, link_tx, config));
+    ctx.add_child(CheckerContext::new(|| expected.into_iter(), rx));
+    let executed = ctx
+        .initialize(
+            InitializationOptionsBuilder::default()
+                .run_flavor_inference(true)
+                .build()
+                .unwrap(),
+        )
+        .unwrap()
+        .run(RunOptions::default());
+    executed.elapsed_cycles().unwrap()
+}
+
+#[test]
+fn link_width_test() {
+    const LINK_CAPACITY: usize = 4;
+    const NUM_PKTS: usize = 16;
+    const LATENCY: u64 = 3;
+    let pkt_bytes = LINK_CAPACITY * std::mem::size_of::<f64>();
+    let wide = LinkConfig::new(LATENCY, pkt_bytes);
+    let narrow = LinkConfig::new(LATENCY, pkt_bytes / 4);
+    assert!(wide.serialization_cycles(pkt_bytes) == 1);
+    assert!(narrow.serialization_cycles(pkt_bytes) == 4);
+    let wide_cycles = run_link(wide, LINK_CAPACITY, NUM_PKTS);
+    let narrow_cycles = run_link(narrow, LINK_CAPACITY, NUM_PKTS);
+    println!(
+        "Wide:{:?} cycles|Narrow:{:?} cycles",
+        wide_cycles, narrow_cycles
+    );
+    assert!(wide_cycles >= NUM_PKTS as u64 + LATENCY);
+    assert!(narrow_cycles >= 4 * NUM_PKTS as u64 + LATENCY);
+    assert!(narrow_cycles > wide_cycles);
+}
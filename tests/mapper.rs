@@ -0,0 +1,36 @@
+use dgemm::{
+    gemm::Dataflow,
+    mapper::{Mapping, Tiling},
+    mesh::Hop,
+    perf::operands,
+    trace::clean_trace,
+};
+use ndarray::*;
+
+#[test]
+fn mapper_gemm_test() {
+    const M: usize = 10;
+    const K: usize = 24;
+    const N: usize = 20;
+    const DIMS: [usize; 2] = [2, 2];
+    const LINK_CAPACITY: usize = 4;
+    const BUFFER_CAPACITY: usize = 2;
+
+    clean_trace();
+    let (x, w) = operands([M, K, N]);
+    let mapping = Mapping::new(x.clone(), w.clone(), DIMS, LINK_CAPACITY, BUFFER_CAPACITY);
+    // K and N exceed what a 2x2 mesh holds, so several passes are needed
+    assert_eq!(
+        mapping.tiling(),
+        Tiling {
+            tk: 4,
+            tn: 4,
+            k_passes: 3,
+            n_passes: 3
+        }
+    );
+    assert_eq!(mapping.node_weights(0, 0)[3], w.slice(s![4..8, 4..8]));
+    let (y, cycles) = mapping.run(Hop::Ideal, Dataflow::Systolic);
+    println!("Passes:{:?}|Took {:?} cycles", mapping.num_passes(), cycles);
+    assert_eq!(y, x.dot(&w));
+}
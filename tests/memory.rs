@@ -0,0 +1,58 @@
+use std::sync::{Arc, Mutex};
+
+use dgemm::{
+    memory::{DramConfig, MemStats, Memory},
+    trace::clean_trace,
+};
+use ndarray::*;
+
+mod common;
+
+const FEATURES: usize = 4;
+const OUTSTANDING: usize = 4;
+const NUM_INPUTS: usize = 16;
+const DIMS: [usize; 2] = [2, 2];
+
+/// Linear layer whose activations are read from and outputs written to one memory
+fn mem_linear(config: DramConfig) -> (Option<u64>, MemStats) {
+    let x = Array::from_shape_fn([NUM_INPUTS, DIMS[0] * FEATURES], |(i, j)| {
+        ((i + j) % 5) as f64
+    });
+    let w = Array::from_shape_fn([DIMS[0] * FEATURES, DIMS[1] * FEATURES], |(i, j)| {
+        ((i * j) % 3) as f64 - 1.0
+    });
+    let stats = Arc::new(Mutex::new(MemStats::default()));
+    let cycles = common::stored_linear(x, w, DIMS, OUTSTANDING, |contents, ports, ctx| {
+        let (requests, responses) = ports;
+        ctx.add_child(Memory::new(
+            contents,
+            config,
+            requests,
+            responses,
+            stats.clone(),
+        ));
+    });
+    let stats = *stats.lock().unwrap();
+    (cycles, stats)
+}
+
+#[test]
+fn memory_bandwidth_test() {
+    clean_trace();
+    let wide_config = DramConfig::new(4, 4, 1024, 32);
+    let narrow_config = DramConfig::new(1, 2, 1024, 2).with_burst(32);
+    let (wide, wide_stats) = mem_linear(wide_config);
+    let (narrow, narrow_stats) = mem_linear(narrow_config);
+    println!("Wide:{:?}|{:?}", wide, wide_stats);
+    println!("Narrow:{:?}|{:?}", narrow, narrow_stats);
+    assert_eq!(wide_stats.bytes_read, narrow_stats.bytes_read);
+    assert_eq!(wide_stats.bytes_written, narrow_stats.bytes_written);
+    // Streams mostly hit the open row
+    assert!(narrow_stats.hit_rate() > 0.5);
+    // Every byte crosses the capped data bus, so the narrow device is the slower one
+    let nbytes = narrow_stats.bytes_read + narrow_stats.bytes_written;
+    let (narrow, wide) = (narrow.unwrap(), wide.unwrap());
+    assert!(narrow >= nbytes.div_ceil(narrow_config.peak_bytes_per_cycle()) as u64);
+    assert!(wide >= nbytes.div_ceil(wide_config.peak_bytes_per_cycle()) as u64);
+    assert!(narrow > wide);
+}
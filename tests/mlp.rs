@@ -2,7 +2,17 @@ use dam::{
     simulation::{InitializationOptionsBuilder, ProgramBuilder, RunOptions},
     utility_contexts::{CheckerContext, GeneratorContext, PrinterContext},
 };
-use dgemm::{actfn::Actfn, gemv::GEMV};
+use dgemm::{
+    actfn::Actfn,
+    gemm::Dataflow,
+    gemv::{GEMV, GemvTracks},
+    mesh::Hop,
+    mlp::{Layer, Mlp, MlpMapping},
+    perf::operands,
+    trace::{clean_trace_dir, get_prefixed_trace_descriptors},
+};
+use ndarray::Array;
+use strum::EnumCount;
 
 fn relu(input: f64) -> f64 {
     input.max(0.0)
@@ -14,6 +24,10 @@ fn matmul_relu_test() {
     const NUM_FEATURES: usize = 128;
     const NUM_OUTPUTS: usize = 8;
     const IS_PRINT: bool = false;
+    clean_trace_dir("gemv_relu");
+    let processes = vec![("gemv".to_string(), vec!["gemv0".to_string()])];
+    let tuuids =
+        get_prefixed_trace_descriptors::<{ GemvTracks::COUNT }>(processes, 2, 1, "gemv_relu/");
     let mut ctx = ProgramBuilder::default();
     let (x_send, x_recv) = ctx.bounded::<f64>(NUM_FEATURES);
     let mut input_vec = Vec::with_capacity(NUM_INPUTS * NUM_FEATURES);
@@ -34,7 +48,11 @@ fn matmul_relu_test() {
     let bias_mat = biases.clone();
     let bias_mat = bias_mat.to_shape((NUM_OUTPUTS, 1)).unwrap();
     // Context2: GEMV
-    ctx.add_child(GEMV::new(x_recv, mm_send, weights.clone(), biases, 1));
+    ctx.add_child(
+        GEMV::new(x_recv, mm_send, weights.clone(), biases, 1)
+            .with_trace(0, tuuids[0])
+            .with_trace_prefix("gemv_relu/"),
+    );
     let (act_send, act_recv) = ctx.bounded::<f64>(NUM_FEATURES);
     // Context3: Act fn
     ctx.add_child(Actfn::new(mm_recv, act_send, 1, relu));
@@ -63,5 +81,39 @@ fn matmul_relu_test() {
         .unwrap()
         .run(RunOptions::default());
     println!("Took {:?} cycles", executed.elapsed_cycles());
-    // assert!(1 == 2);
+    assert!(std::path::Path::new("artifacts/trace/gemv_relu/gemv_0_.perfetto").exists());
+}
+
+#[test]
+fn mesh_mlp_test() {
+    const M: usize = 6;
+    const LINK_CAPACITY: usize = 4;
+    const BUFFER_CAPACITY: usize = 2;
+    const FEATURES: [usize; 4] = [8, 16, 8, 4];
+
+    clean_trace_dir("mlp");
+    let (x, _) = operands([M, FEATURES[0], FEATURES[1]]);
+    let layers = Vec::from_iter(FEATURES.windows(2).enumerate().map(|(l, f)| {
+        let (_, w) = operands([M, f[0], f[1]]);
+        let b = Array::from_shape_fn(f[1], |i| (i % 2) as f64);
+        let act: fn(f64) -> f64 = if l + 2 < FEATURES.len() { relu } else { |x| x };
+        Layer::new(w, b, act)
+    }));
+    let mlp = Mlp::new(layers, LINK_CAPACITY, BUFFER_CAPACITY).with_trace_prefix("mlp/");
+    let ref_out = mlp.reference(&x);
+    let (y, same_mesh) = mlp.run(
+        x.clone(),
+        MlpMapping::SameMesh { dims: [2, 2] },
+        Hop::Ideal,
+        Dataflow::Systolic,
+    );
+    assert_eq!(y, ref_out);
+    let (y, pipeline) = mlp.run(
+        x,
+        MlpMapping::Pipeline { tile: 4 },
+        Hop::Ideal,
+        Dataflow::Systolic,
+    );
+    assert_eq!(y, ref_out);
+    println!("SameMesh:{:?}|Pipeline:{:?}", same_mesh, pipeline);
 }
@@ -0,0 +1,98 @@
+use dam::{
+    simulation::{InitializationOptionsBuilder, ProgramBuilder, RunOptions},
+    utility_contexts::CheckerContext,
+};
+use dgemm::{
+    producer::Producer,
+    router::{Flit, Port, Routing, noc_conn, routed_noc_conn},
+    trace::clean_trace,
+};
+use ndarray::prelude::*;
+use strum::EnumCount;
+
+const DIMS: [usize; 2] = [3, 3];
+const BUFFER_DEPTH: usize = 4;
+const NUM_FLITS: usize = 8;
+const FLIT_SIZE: usize = 4;
+
+/// Node (r, c) sends `NUM_FLITS` flits to node (c, r), returns the cycles
+fn run_transpose(routing: Option<&dyn Fn(usize) -> Routing>) -> u64 {
+    let num_nodes: usize = DIMS.iter().product();
+    clean_trace();
+    let thread_names = Vec::from_iter((0..num_nodes).map(|n| format!("router{n}", n = n)));
+    let processes = vec![("noc".to_string(), thread_names)];
+    let tuuids =
+        dgemm::trace::get_trace_descriptors::<{ Port::COUNT }>(processes, num_nodes + 1, num_nodes);
+    let mut ctx = ProgramBuilder::default();
+    let (inject_sends, eject_recvs) = match routing {
+        Some(routing) => routed_noc_conn::<f64>(DIMS, BUFFER_DEPTH, tuuids, routing, &mut ctx),
+        None => noc_conn::<f64>(DIMS, BUFFER_DEPTH, tuuids, &mut ctx),
+    };
+    let transpose = |node: usize| (node % DIMS[1]) * DIMS[1] + node / DIMS[1];
+    let mk_flits = |src: usize| {
+        Vec::from_iter((0..NUM_FLITS).map(|i| {
+            let payload = Array::from_elem(FLIT_SIZE, (src * NUM_FLITS + i) as f64);
+            Flit::new(src, transpose(src), payload)
+        }))
+    };
+    for (node, inject_send) in inject_sends.into_iter().enumerate() {
+        let flits = mk_flits(node);
+        ctx.add_child(Producer::new(|| flits.into_iter(), inject_send, node, 0));
+    }
+    for (node, eject_recv) in eject_recvs.into_iter().enumerate() {
+        let expected = mk_flits(transpose(node));
+        ctx.add_child(CheckerContext::new(|| expected.into_iter(), eject_recv));
+    }
+    let executed = ctx
+        .initialize(
+            InitializationOptionsBuilder::default()
+                .run_flavor_inference(true)
+                .build()
+                .unwrap(),
+        )
+        .unwrap()
+        .run(RunOptions::default());
+    executed.elapsed_cycles().unwrap()
+}
+
+#[test]
+fn noc_transpose_test() {
+    let diameter = (DIMS[0] - 1 + DIMS[1] - 1) as u64;
+    let cycles = run_transpose(None);
+    println!("Took {:?} cycles", cycles);
+    // Corner to corner is the longest route. Under XY routing the links next to the
+    // corners carry two flows, e.g. (0, 1) and (0, 2) both go west into (0, 0).
+    let drained = 2 * NUM_FLITS as u64 + diameter;
+    assert!(cycles >= drained);
+    // The routers notice within a diameter that the NoC drained
+    assert!(cycles <= drained + diameter);
+
+    // YX routing as tables, rows first and columns second
+    let yx = |node: usize| {
+        let (row, col) = (node / DIMS[1], node % DIMS[1]);
+        let ports = Vec::from_iter((0..DIMS[0] * DIMS[1]).map(|dest| {
+            let (drow, dcol) = (dest / DIMS[1], dest % DIMS[1]);
+            let port = if drow > row {
+                Port::South
+            } else if drow < row {
+                Port::North
+            } else if dcol > col {
+                Port::East
+            } else if dcol < col {
+                Port::West
+            } else {
+                Port::Local
+            };
+            port as usize
+        }));
+        Routing::Table(ports)
+    };
+    let table = yx(0);
+    assert_eq!(table.route(2), Port::East as usize);
+    assert_eq!(table.route(8), Port::South as usize);
+    assert!(std::panic::catch_unwind(|| table.route(9)).is_err());
+    // The transpose is symmetric, so YX sees the same contention as XY
+    let table_cycles = run_transpose(Some(&yx));
+    println!("Table routing took {:?} cycles", table_cycles);
+    assert_eq!(table_cycles, cycles);
+}
@@ -0,0 +1,220 @@
+use std::sync::{Arc, Mutex};
+
+use dam::{
+    simulation::{InitializationOptionsBuilder, ProgramBuilder, RunOptions},
+    utility_contexts::GeneratorContext,
+};
+use dgemm::{
+    actfn::Builtin,
+    attention::{Attention, RowScatter},
+    consumer::Collector,
+    elementwise::{Elementwise, ElementwiseOp, SyncStats},
+    gemm::{Dataflow, Tracks},
+    mapper::Mapping,
+    mesh::{Hop, MeshHarness, MeshSink, take_left_inputs},
+    norm::{Norm, NormConstants, NormKind, reference},
+    trace::{clean_trace, get_trace_descriptors},
+};
+use ndarray::*;
+use strum::EnumCount;
+
+#[test]
+fn norm_reference_test() {
+    const NUM_ROWS: usize = 6;
+    const FEATURES: usize = 8;
+    const LANES: usize = 4;
+    const REDUCTION_LATENCY: u64 = 3;
+    const EPS: f64 = 1e-5;
+
+    // The last row sits on a large offset, `E[x^2] - E[x]^2` loses its variance
+    let x = Array::from_shape_fn([NUM_ROWS, FEATURES], |(i, j)| {
+        let offset = if i + 1 == NUM_ROWS { 1e4 } else { 0.0 };
+        offset + ((i * 7 + j * 3) as f64).sin()
+    });
+    let gamma = Array::from_shape_fn(FEATURES, |j| 1.0 + 0.1 * j as f64);
+    let beta = Array::from_shape_fn(FEATURES, |j| 0.5 - 0.1 * j as f64);
+    // Statistics straight from ndarray
+    let mean = x.mean_axis(Axis(1)).unwrap().insert_axis(Axis(1));
+    let var = x.var_axis(Axis(1), 0.0).insert_axis(Axis(1));
+    let layer_norm = (&x - &mean) / (var + EPS).mapv(f64::sqrt) * &gamma + &beta;
+    let mean_sq = x
+        .mapv(|v| v * v)
+        .mean_axis(Axis(1))
+        .unwrap()
+        .insert_axis(Axis(1));
+    let rms_norm = &x / (mean_sq + EPS).mapv(f64::sqrt) * &gamma + &beta;
+
+    for (kind, expected) in [
+        (NormKind::LayerNorm, layer_norm),
+        (NormKind::RmsNorm, rms_norm),
+    ] {
+        let constants = NormConstants::new(kind, EPS, LANES, REDUCTION_LATENCY);
+        let diff = reference(&x, &gamma, &beta, &constants) - &expected;
+        assert!(diff.iter().all(|e| e.abs() < 1e-9));
+
+        let mut ctx = ProgramBuilder::default();
+        let (row_send, row_recv) = ctx.bounded(2);
+        let (out_send, out_recv) = ctx.bounded(2);
+        let rows = Vec::from_iter(x.rows().into_iter().map(|r| r.to_owned()));
+        ctx.add_child(GeneratorContext::new(|| rows.into_iter(), row_send));
+        ctx.add_child(Norm::new(
+            row_recv,
+            out_send,
+            gamma.clone(),
+            beta.clone(),
+            constants,
+        ));
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        ctx.add_child(Collector::new(out_recv, buffer.clone()));
+        let executed = ctx
+            .initialize(
+                InitializationOptionsBuilder::default()
+                    .run_flavor_inference(true)
+                    .build()
+                    .unwrap(),
+            )
+            .unwrap()
+            .run(RunOptions::default());
+        println!("{:?}|Took {:?} cycles", kind, executed.elapsed_cycles());
+        // Rows overlap, only the last one pays the full latency
+        let interval = constants.row_interval(FEATURES);
+        let min_cycles = (NUM_ROWS as u64 - 1) * interval + constants.row_latency(FEATURES);
+        assert!(executed.elapsed_cycles().unwrap() >= min_cycles);
+        let outputs = buffer.lock().unwrap();
+        let views = Vec::from_iter(outputs.iter().map(|r| r.view()));
+        let y = stack(Axis(0), &views).unwrap();
+        assert!((y - &expected).iter().all(|e| e.abs() < 1e-9));
+    }
+}
+
+#[test]
+fn transformer_block_test() {
+    const SEQ_LEN: usize = 8;
+    const NUM_HEADS: usize = 2;
+    const HEAD_DIM: usize = 4;
+    const D_MODEL: usize = NUM_HEADS * HEAD_DIM;
+    const D_FF: usize = 8;
+    const DIMS: [usize; 2] = [2, 2];
+    const LINK_CAPACITY: usize = 4;
+    const BUFFER_CAPACITY: usize = 2;
+    const EPS: f64 = 1e-5;
+
+    clean_trace();
+    let x = Array::from_shape_fn([SEQ_LEN, D_MODEL], |(i, j)| {
+        ((i * 3 + j) % 5) as f64 * 0.25 - 0.5
+    });
+    let proj = |l: usize| {
+        Array::from_shape_fn([D_MODEL, D_MODEL], |(i, j)| {
+            ((i + 2 * j + l) % 7) as f64 * 0.1 - 0.3
+        })
+    };
+    let (wq, wk, wv) = (proj(0), proj(1), proj(2));
+    let w1 = Array::from_shape_fn([D_MODEL, D_FF], |(i, j)| ((i * j) % 5) as f64 * 0.1 - 0.2);
+    let w2 = Array::from_shape_fn([D_FF, D_MODEL], |(i, j)| ((i + j) % 3) as f64 * 0.2 - 0.2);
+    let b1 = Array::from_shape_fn(D_FF, |j| 0.05 * j as f64);
+    let b2 = Array::from_shape_fn(D_MODEL, |j| 0.1 - 0.02 * j as f64);
+    let gamma = Array::from_shape_fn(D_MODEL, |j| 1.0 - 0.05 * j as f64);
+    let beta = Array::from_shape_fn(D_MODEL, |j| 0.1 * j as f64 - 0.2);
+    let gelu: fn(f64) -> f64 = |v| Builtin::Gelu.eval(v);
+    let attention = Attention::new(NUM_HEADS, HEAD_DIM, DIMS, LINK_CAPACITY, BUFFER_CAPACITY);
+
+    // FFN(LayerNorm(X + Attention(X))) straight from ndarray
+    let a = attention.reference(&x.dot(&wq), &x.dot(&wk), &x.dot(&wv));
+    let h = &x + &a;
+    let mean = h.mean_axis(Axis(1)).unwrap().insert_axis(Axis(1));
+    let var = h.var_axis(Axis(1), 0.0).insert_axis(Axis(1));
+    let h = (&h - &mean) / (var + EPS).mapv(f64::sqrt) * &gamma + &beta;
+    let expected = (h.dot(&w1) + &b1).mapv(gelu).dot(&w2) + &b2;
+
+    // Projections and attention on the mesh
+    let project = |w| {
+        let mapping = Mapping::new(x.clone(), w, DIMS, LINK_CAPACITY, BUFFER_CAPACITY);
+        mapping.run(Hop::Ideal, Dataflow::Systolic).0
+    };
+    let (q, k, v) = (project(wq), project(wk), project(wv));
+    let (a, report) = attention.run(&q, &k, &v, Hop::Ideal, Dataflow::Systolic);
+
+    // Residual add -> LayerNorm -> FFN, the two FFN meshes chained
+    let ffn1 = Mapping::streamed(SEQ_LEN, w1, DIMS, LINK_CAPACITY, BUFFER_CAPACITY);
+    let ffn2 = Mapping::streamed(SEQ_LEN, w2, DIMS, LINK_CAPACITY, BUFFER_CAPACITY);
+    let (t1, t2) = (ffn1.tiling(), ffn2.tiling());
+    assert!(ffn1.num_passes() == 1 && ffn2.num_passes() == 1 && t1.tn == t2.tk);
+    let num_nodes = 2 * DIMS[0] * DIMS[1];
+    let thread_names = Vec::from_iter((0..num_nodes).map(|n| format!("xpu{n}")));
+    let processes = vec![("xpu".to_string(), thread_names)];
+    let tuuids = get_trace_descriptors::<{ Tracks::COUNT }>(processes, num_nodes + 1, num_nodes);
+    let mut ctx = ProgramBuilder::default();
+    let (hop, dataflow) = (Hop::Ideal, Dataflow::Systolic);
+    let (mut prods1, cons1) = ffn1.add_nodes(0, 0, hop, dataflow, &tuuids, 0, &mut ctx);
+    let (mut prods2, cons2) = ffn2.add_nodes(0, 0, hop, dataflow, &tuuids, num_nodes / 2, &mut ctx);
+
+    let (x_send, x_recv) = ctx.bounded(BUFFER_CAPACITY);
+    let (a_send, a_recv) = ctx.bounded(BUFFER_CAPACITY);
+    let rows = |y: &Array2<f64>| Vec::from_iter(y.rows().into_iter().map(|r| r.to_owned()));
+    let (x_rows, a_rows) = (rows(&x), rows(&a));
+    ctx.add_child(GeneratorContext::new(move || x_rows.into_iter(), x_send));
+    ctx.add_child(GeneratorContext::new(move || a_rows.into_iter(), a_send));
+    let (sum_send, sum_recv) = ctx.bounded(BUFFER_CAPACITY);
+    let stats = Arc::new(Mutex::new(SyncStats::default()));
+    ctx.add_child(Elementwise::new(
+        x_recv,
+        a_recv,
+        sum_send,
+        ElementwiseOp::Add,
+        1,
+        stats.clone(),
+    ));
+    let (norm_send, norm_recv) = ctx.bounded(BUFFER_CAPACITY);
+    let constants = NormConstants::new(NormKind::LayerNorm, EPS, 4, 3);
+    ctx.add_child(Norm::new(sum_recv, norm_send, gamma, beta, constants));
+    ctx.add_child(RowScatter::new(
+        norm_recv,
+        take_left_inputs(&mut prods1),
+        t1.tk,
+        LINK_CAPACITY,
+        ffn1.num_inputs(),
+    ));
+    let chained = |mapping: &Mapping<f64>| {
+        let tiling = mapping.tiling();
+        MeshHarness::chained(
+            DIMS,
+            LINK_CAPACITY,
+            mapping.num_inputs(),
+            tiling.tk,
+            tiling.tn,
+        )
+    };
+    let ffn2_inputs = take_left_inputs(&mut prods2);
+    chained(&ffn1).with_epilogue(b1, gelu).attach(
+        prods1,
+        cons1,
+        MeshSink::Forward(ffn2_inputs),
+        &mut ctx,
+    );
+    let output =
+        chained(&ffn2)
+            .with_epilogue(b2, |v| v)
+            .attach(prods2, cons2, MeshSink::Collect, &mut ctx);
+    let executed = ctx
+        .initialize(
+            InitializationOptionsBuilder::default()
+                .run_flavor_inference(true)
+                .build()
+                .unwrap(),
+        )
+        .unwrap()
+        .run(RunOptions::default());
+    println!(
+        "Attention {:?}|Block took {:?} cycles",
+        report,
+        executed.elapsed_cycles()
+    );
+    let y = output.to_array().slice(s![..SEQ_LEN, ..]).to_owned();
+    // The mesh sums in a different order than ndarray
+    assert!((y - &expected).iter().all(|e| e.abs() < 1e-9));
+    assert!(stats.lock().unwrap().is_aligned());
+    // The FFN only starts on a row once the norm has written it
+    let norm_cycles =
+        (SEQ_LEN as u64 - 1) * constants.row_interval(D_MODEL) + constants.row_latency(D_MODEL);
+    assert!(executed.elapsed_cycles().unwrap() > norm_cycles);
+}
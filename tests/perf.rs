@@ -0,0 +1,60 @@
+use dgemm::{
+    gemm::Dataflow,
+    link::LinkConfig,
+    mesh::Hop,
+    perf::{PerfParams, estimate, validate},
+    trace::clean_trace,
+};
+
+#[test]
+fn perf_model_test() {
+    clean_trace();
+    let multicast = Dataflow::Multicast { bus_latency: 3 };
+    let sweep = [
+        (PerfParams::new([8, 4, 4], [1, 1], 4, 2), Hop::Ideal),
+        (PerfParams::new([32, 16, 8], [2, 4], 4, 4), Hop::Ideal),
+        (
+            PerfParams::new([24, 8, 8], [4, 4], 8, 2).with_initiation_interval(2),
+            Hop::Ideal,
+        ),
+        (
+            PerfParams::new([16, 8, 12], [2, 2], 4, 2).with_dataflow(multicast),
+            Hop::Ideal,
+        ),
+        // Not covered by the model, the error shows what the links cost
+        (
+            PerfParams::new([16, 8, 8], [2, 2], 4, 2),
+            Hop::Link(LinkConfig::new(4, 8)),
+        ),
+    ];
+    for (params, hop) in sweep {
+        let validation = validate(&params, hop);
+        println!(
+            "{:?}|{:?}|Model {}|Simulated {}|Error {:.3}|Utilization {:.3}",
+            params.shape,
+            params.dims,
+            validation.estimate.cycles,
+            validation.simulated,
+            validation.relative_error(),
+            validation.estimate.mean_utilization(validation.simulated)
+        );
+        if matches!(hop, Hop::Ideal) {
+            assert_eq!(validation.estimate.cycles, validation.simulated);
+        } else {
+            assert!(validation.relative_error() < 0.0);
+        }
+    }
+
+    // K = 6 fits one pass of 4 row tiles, the second mesh row holds 2 rows of padding
+    let params = PerfParams::new([8, 6, 4], [2, 2], 4, 2);
+    let est = estimate(&params);
+    assert_eq!((est.tiling.tk, est.tiling.tn), (4, 2));
+    assert_eq!(
+        est.useful_macs,
+        vec![8 * 4 * 2, 8 * 4 * 2, 8 * 2 * 2, 8 * 2 * 2]
+    );
+    assert_eq!(est.traffic.inputs, 2 * 8);
+    assert_eq!(est.traffic.forwarded, 2 * 8);
+    assert_eq!(est.traffic.vertical, 2 * 4);
+    assert_eq!(est.traffic.total(), 16 + 16 + 8 + 8 + 8);
+}
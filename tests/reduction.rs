@@ -0,0 +1,71 @@
+use dam::{
+    simulation::{InitializationOptionsBuilder, ProgramBuilder, RunOptions},
+    utility_contexts::{CheckerContext, GeneratorContext},
+};
+use dgemm::reduction::{AdderTreeConfig, split_k_gemv};
+use ndarray::*;
+
+#[test]
+fn split_k_gemv_test() {
+    const NUM_INPUTS: usize = 4;
+    const NUM_FEATURES: usize = 30;
+    const NUM_OUTPUTS: usize = 6;
+    const LEVEL_LATENCY: u64 = 2;
+
+    let x = Array::from_shape_fn([NUM_INPUTS, NUM_FEATURES], |(i, j)| {
+        ((i * 3 + j) % 7) as f64
+    });
+    let weights = Array::from_shape_fn([NUM_OUTPUTS, NUM_FEATURES], |(i, j)| {
+        ((i + 2 * j) % 5) as f64 - 2.0
+    });
+    let biases = Array::from_shape_fn(NUM_OUTPUTS, |i| i as f64);
+    let ref_out = x.dot(&weights.t()) + &biases;
+
+    let tree = AdderTreeConfig::new(2, LEVEL_LATENCY);
+    assert_eq!(tree.levels(1), 0);
+    assert_eq!(tree.levels(4), 2);
+    assert_eq!(tree.levels(7), 3);
+    assert_eq!(AdderTreeConfig::new(4, 1).levels(7), 2);
+    assert_eq!(tree.reduce(vec![1.0, 2.0, 3.0, 4.0, 5.0]), 15.0);
+    let mut cycles = Vec::new();
+    for parts in [1, 2, 4, 7] {
+        let mut ctx = ProgramBuilder::default();
+        let (x_send, x_recv) = ctx.bounded::<Array1<f64>>(2);
+        let (y_send, y_recv) = ctx.bounded::<f64>(NUM_OUTPUTS);
+        let inputs = Vec::from_iter(x.outer_iter().map(|row| row.to_owned()));
+        ctx.add_child(GeneratorContext::new(|| inputs.into_iter(), x_send));
+        let ranges = split_k_gemv(
+            x_recv,
+            y_send,
+            weights.clone(),
+            biases.clone(),
+            parts,
+            1,
+            tree,
+            &mut ctx,
+        );
+        assert_eq!(ranges.len(), parts);
+        assert_eq!(ranges.iter().map(|r| r.len()).sum::<usize>(), NUM_FEATURES);
+        let expected = ref_out.clone();
+        ctx.add_child(CheckerContext::new(move || expected.into_iter(), y_recv));
+        let executed = ctx
+            .initialize(
+                InitializationOptionsBuilder::default()
+                    .run_flavor_inference(true)
+                    .build()
+                    .unwrap(),
+            )
+            .unwrap()
+            .run(RunOptions::default());
+        let elapsed = executed.elapsed_cycles().unwrap();
+        println!("Parts:{parts}|Took {elapsed} cycles");
+        // A vector takes its longest slice to feed, or its outputs to sum
+        let per_vector = NUM_FEATURES.div_ceil(parts).max(NUM_OUTPUTS);
+        let min_cycles = (NUM_INPUTS * per_vector) as u64 + tree.latency(parts);
+        assert!(elapsed >= min_cycles);
+        cycles.push(elapsed);
+    }
+    // Wider units are faster until the outputs and the tree dominate
+    assert!(cycles.windows(2).all(|pair| pair[1] < pair[0]));
+    assert!(cycles[3] * 3 < cycles[0]);
+}
@@ -0,0 +1,46 @@
+use dgemm::{
+    gemm::Dataflow,
+    mapper::Mapping,
+    mesh::Hop,
+    perf::operands,
+    scheduler::{LoopOrder, OffChipConfig, TileSchedule},
+    trace::clean_trace,
+};
+
+#[test]
+fn tile_schedule_test() {
+    const M: usize = 8;
+    const K: usize = 16;
+    const N: usize = 24;
+    const DIMS: [usize; 2] = [2, 2];
+    const LINK_CAPACITY: usize = 4;
+    const BUFFER_CAPACITY: usize = 2;
+    const ELEM_BYTES: usize = std::mem::size_of::<f64>();
+
+    clean_trace();
+    let (x, w) = operands([M, K, N]);
+    let ref_out = x.dot(&w);
+    let offchip = OffChipConfig::new(16, 10);
+    let mut reports = Vec::new();
+    for order in [LoopOrder::OutputTileMajor, LoopOrder::KMajor] {
+        let mapping = Mapping::new(x.clone(), w.clone(), DIMS, LINK_CAPACITY, BUFFER_CAPACITY);
+        let tiling = mapping.tiling();
+        assert!(tiling.k_passes == 2 && tiling.n_passes == 3);
+        let (y, report) =
+            TileSchedule::new(mapping, order, offchip).run(Hop::Ideal, Dataflow::Systolic);
+        assert_eq!(y, ref_out);
+        println!("{:?}", report);
+        reports.push(report);
+    }
+    let (out_major, k_major) = (reports[0].traffic, reports[1].traffic);
+    assert_eq!(out_major.weights, K * N * ELEM_BYTES);
+    assert_eq!(out_major.weights, k_major.weights);
+    // Activations are read once per output tile or once in total
+    assert_eq!(out_major.activations, 3 * M * K * ELEM_BYTES);
+    assert_eq!(k_major.activations, M * K * ELEM_BYTES);
+    // Partial sums of the first K-tile are spilled and read back
+    assert_eq!(out_major.psum_writes + out_major.psum_reads, 0);
+    assert_eq!(k_major.psum_writes, M * N * ELEM_BYTES);
+    assert_eq!(k_major.psum_reads, M * N * ELEM_BYTES);
+    assert_eq!(out_major.outputs, k_major.outputs);
+}
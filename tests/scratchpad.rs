@@ -0,0 +1,69 @@
+use std::sync::{Arc, Mutex};
+
+use dgemm::{
+    scratchpad::{BankMapping, Scratchpad, ScratchpadConfig, SpadStats},
+    trace::clean_trace,
+};
+use ndarray::*;
+
+mod common;
+
+use common::LINK_CAPACITY;
+
+const FEATURES: usize = 4;
+const OUTSTANDING: usize = 2;
+const NUM_INPUTS: usize = 16;
+const DIMS: [usize; 2] = [2, 2];
+
+/// Linear layer whose activations are read from and outputs written back to one scratchpad
+fn spad_linear(config: ScratchpadConfig) -> (Option<u64>, SpadStats) {
+    let x = Array::from_shape_fn([NUM_INPUTS, DIMS[0] * FEATURES], |(i, j)| {
+        ((i + 2 * j) % 5) as f64
+    });
+    let w = Array::from_shape_fn([DIMS[0] * FEATURES, DIMS[1] * FEATURES], |(i, j)| {
+        ((i + j) % 3) as f64 - 1.0
+    });
+    let stats = Arc::new(Mutex::new(SpadStats::default()));
+    let cycles = common::stored_linear(x, w, DIMS, OUTSTANDING, |contents, ports, ctx| {
+        let (requests, responses) = ports;
+        ctx.add_child(Scratchpad::new(
+            contents,
+            config,
+            requests,
+            responses,
+            stats.clone(),
+        ));
+    });
+    let stats = *stats.lock().unwrap();
+    (cycles, stats)
+}
+
+#[test]
+fn scratchpad_conflict_test() {
+    clean_trace();
+    let ports = DIMS[0] + DIMS[1];
+    let interleaved = ScratchpadConfig::new(2 * LINK_CAPACITY, ports, BankMapping::Interleaved);
+    // Every address in bank 0
+    let single_bank = ScratchpadConfig::new(
+        2 * LINK_CAPACITY,
+        ports,
+        BankMapping::Blocked {
+            bank_size: usize::MAX,
+        },
+    );
+    let (fast, fast_stats) = spad_linear(interleaved);
+    let (slow, slow_stats) = spad_linear(single_bank);
+    println!("Interleaved:{:?}|{:?}", fast, fast_stats);
+    println!("Single bank:{:?}|{:?}", slow, slow_stats);
+    assert_eq!(fast_stats.reads, slow_stats.reads);
+    assert_eq!(fast_stats.writes, slow_stats.writes);
+    assert_eq!(fast_stats.accesses, slow_stats.accesses);
+    // A packet is LINK_CAPACITY accesses to one bank, all but the first wait
+    let requests = (slow_stats.reads + slow_stats.writes) as u64;
+    assert!(slow_stats.conflict_cycles >= requests * (LINK_CAPACITY as u64 - 1));
+    assert!(fast_stats.conflict_cycles < slow_stats.conflict_cycles);
+    assert!(slow.unwrap() >= slow_stats.accesses as u64);
+    // Elements 3..7 span the lines 1, 2 and 3 of two elements
+    let config = ScratchpadConfig::new(4, 1, BankMapping::Xor).with_bank_width(2);
+    assert_eq!(config.bank_accesses(3, 4), vec![0, 1, 1, 1]);
+}
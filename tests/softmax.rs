@@ -0,0 +1,64 @@
+use std::sync::{Arc, Mutex};
+
+use dam::{
+    simulation::{InitializationOptionsBuilder, ProgramBuilder, RunOptions},
+    utility_contexts::GeneratorContext,
+};
+use dgemm::{
+    consumer::Collector,
+    softmax::{Softmax, SoftmaxConstants, SoftmaxMode, softmax},
+};
+use ndarray::*;
+
+#[test]
+fn softmax_modes_test() {
+    const NUM_ROWS: usize = 4;
+    const ROW_LEN: usize = 16;
+    const LANES: usize = 4;
+
+    // Ascending rows grow the running max on every element
+    let rows = Vec::from_iter(
+        (0..NUM_ROWS).map(|r| Array::from_iter((0..ROW_LEN).map(|i| (i as f64 * 0.5) - r as f64))),
+    );
+    for (exp_throughput, expected) in [(1, [24, 47]), (ROW_LEN, [12, 8])] {
+        for (mode, cycles) in [SoftmaxMode::TwoPass, SoftmaxMode::Online]
+            .into_iter()
+            .zip(expected)
+        {
+            let constants = SoftmaxConstants::new(mode, LANES, exp_throughput);
+            assert_eq!(constants.normalize(rows[0].view()).1, cycles);
+
+            let mut ctx = ProgramBuilder::default();
+            let (row_send, row_recv) = ctx.bounded(2);
+            let (out_send, out_recv) = ctx.bounded(2);
+            let inputs = rows.clone();
+            ctx.add_child(GeneratorContext::new(|| inputs.into_iter(), row_send));
+            ctx.add_child(Softmax::new(row_recv, out_send, constants));
+            let buffer = Arc::new(Mutex::new(Vec::new()));
+            ctx.add_child(Collector::new(out_recv, buffer.clone()));
+            let executed = ctx
+                .initialize(
+                    InitializationOptionsBuilder::default()
+                        .run_flavor_inference(true)
+                        .build()
+                        .unwrap(),
+                )
+                .unwrap()
+                .run(RunOptions::default());
+            println!(
+                "{:?}|{:?}|Took {:?} cycles",
+                mode,
+                exp_throughput,
+                executed.elapsed_cycles()
+            );
+            // Rows are normalized one after the other
+            assert!(executed.elapsed_cycles().unwrap() >= NUM_ROWS as u64 * cycles);
+            let outputs = buffer.lock().unwrap();
+            assert_eq!(outputs.len(), NUM_ROWS);
+            for (out, row) in outputs.iter().zip(rows.iter()) {
+                assert!((out - &softmax(row.view())).iter().all(|e| e.abs() < 1e-12));
+                assert!((out.sum() - 1.0).abs() < 1e-12);
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use dgemm::{
+    gemm::Dataflow,
+    sweep::{Sweep, SweepSpace, to_csv, write_table},
+    trace::{clean_trace, perfetto::Trace},
+};
+use protobuf::Message;
+
+/// Checks that the traces in `dir` only use tracks of the header in `dir`, returns the
+/// number of track events
+fn check_trace_dir(dir: &Path) -> usize {
+    let read = |path: &Path| Trace::parse_from_bytes(&fs::read(path).unwrap()).unwrap();
+    let header = read(&dir.join("header_0_.perfetto"));
+    let tracks = HashSet::<u64>::from_iter(
+        header
+            .packet
+            .iter()
+            .filter(|p| p.has_track_descriptor())
+            .map(|p| p.track_descriptor().uuid()),
+    );
+    let mut events = 0;
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.file_name().unwrap() == "header_0_.perfetto" {
+            continue;
+        }
+        for packet in read(&path).packet.iter().filter(|p| p.has_track_event()) {
+            assert!(tracks.contains(&packet.track_event().track_uuid()));
+            events += 1;
+        }
+    }
+    events
+}
+
+#[test]
+fn sweep_runner_test() {
+    const SHAPE: [usize; 3] = [16, 8, 8];
+
+    clean_trace();
+    let space = SweepSpace {
+        dims: vec![[1, 1], [2, 2]],
+        link_capacity: vec![4, 8],
+        buffer_size: vec![2],
+        initiation_interval: vec![1, 2],
+        dataflow: vec![Dataflow::Systolic, Dataflow::Multicast { bus_latency: 1 }],
+    };
+    let points = space.points(SHAPE);
+    assert_eq!(points.len(), 16);
+    let results = Sweep::new("sweep", SHAPE, space.clone()).with_jobs(4).run();
+    assert_eq!(results.len(), points.len());
+    for (result, params) in results.iter().zip(points.iter()) {
+        assert_eq!(result.params.dims, params.dims);
+        assert_eq!(result.params.link_capacity, params.link_capacity);
+        assert_eq!(
+            result.params.initiation_interval,
+            params.initiation_interval
+        );
+        // Ideal hops, the analytical model is exact
+        assert_eq!(result.cycles, result.estimated_cycles);
+        assert!(result.utilization > 0.0 && result.utilization <= 1.0);
+        assert!(result.trace_bytes > 0 && result.energy_pj > 0.0);
+    }
+    // Running the points one by one gives the same table
+    let sequential = Sweep::new("sweep_seq", SHAPE, space).with_jobs(1).run();
+    let cycles = |rs: &[_]| Vec::from_iter(rs.iter().map(|r: &dgemm::sweep::SweepResult| r.cycles));
+    assert_eq!(cycles(&results), cycles(&sequential));
+
+    // Every point keeps a loadable trace of its own, nothing lands in the root
+    assert!(!Path::new("artifacts/trace/header_0_.perfetto").exists());
+    for idx in [0, points.len() - 1] {
+        let dir = Path::new("artifacts/trace/sweep").join(idx.to_string());
+        assert!(check_trace_dir(&dir) > 0);
+    }
+
+    let csv = to_csv(&results);
+    print!("{csv}");
+    assert_eq!(csv.lines().count(), points.len() + 1);
+    let path = std::env::temp_dir().join("dgemm_sweep.json");
+    write_table(&results, &path).unwrap();
+    let json = std::fs::read_to_string(&path).unwrap();
+    assert!(json.starts_with('[') && json.matches("\"cycles\"").count() == points.len());
+}
@@ -0,0 +1,98 @@
+use dgemm::{
+    gemm::Dataflow,
+    mapper::Mapping,
+    mesh::Hop,
+    perf::operands,
+    systolic::{ModelComparison, NodeModel},
+    trace::clean_trace_dir,
+};
+
+#[test]
+fn pe_level_gemm_test() {
+    const LINK_CAPACITY: usize = 4;
+    const BUFFER_CAPACITY: usize = 2;
+    const PE_LEVEL: NodeModel = NodeModel::PeLevel {
+        mac_latency: 1,
+        fifo_depth: 2,
+    };
+    // [M, K, N], mesh dims and dataflow of every small array
+    let cases = [
+        ([8, 4, 4], [1, 1], Dataflow::Systolic),
+        ([16, 8, 8], [2, 2], Dataflow::Systolic),
+        ([8, 8, 12], [2, 3], Dataflow::Multicast { bus_latency: 1 }),
+    ];
+
+    clean_trace_dir("pe_level");
+    for ([m, k, n], dims, dataflow) in cases {
+        let (x, w) = operands([m, k, n]);
+        let mapping = || {
+            Mapping::new(x.clone(), w.clone(), dims, LINK_CAPACITY, BUFFER_CAPACITY)
+                .with_trace_prefix("pe_level/")
+        };
+        let (y_coarse, coarse) = mapping().run(Hop::Ideal, dataflow);
+        let pe_mapping = mapping().with_node_model(PE_LEVEL);
+        let (y_pe, pe_level) = pe_mapping.run(Hop::Ideal, dataflow);
+        assert_eq!(y_coarse, x.dot(&w));
+        assert_eq!(y_pe, y_coarse);
+        let comparison = ModelComparison { coarse, pe_level };
+        println!(
+            "Shape:{:?}|Dims:{:?}|{:?}|Coarse error {:.3}",
+            [m, k, n],
+            dims,
+            comparison,
+            comparison.relative_error()
+        );
+        // Every PE takes one row of X per cycle
+        let rows = (pe_mapping.num_passes() * pe_mapping.num_inputs()) as u64;
+        assert!(pe_level >= rows);
+    }
+}
+
+#[test]
+fn model_sweep_test() {
+    const LINK_CAPACITY: usize = 4;
+    const DIMS: [usize; 2] = [2, 2];
+    const PE_LEVEL: NodeModel = NodeModel::PeLevel {
+        mac_latency: 1,
+        fifo_depth: 2,
+    };
+    let run = |shape, buffer, ii| {
+        let (x, w) = operands(shape);
+        let mapping = || {
+            Mapping::new(x.clone(), w.clone(), DIMS, LINK_CAPACITY, buffer)
+                .with_trace_prefix("model_sweep/")
+        };
+        let (_, coarse) = mapping()
+            .with_initiation_interval(ii)
+            .run(Hop::Ideal, Dataflow::Systolic);
+        let (_, pe_level) = mapping()
+            .with_node_model(PE_LEVEL)
+            .run(Hop::Ideal, Dataflow::Systolic);
+        (mapping().tiling(), ModelComparison { coarse, pe_level })
+    };
+
+    clean_trace_dir("model_sweep");
+    for shape in [[8, 4, 4], [16, 8, 8], [32, 8, 4]] {
+        let mut errors = Vec::new();
+        for buffer in [1, 2, 4] {
+            for ii in [1, 2, 4] {
+                let (tiling, comparison) = run(shape, buffer, ii);
+                let error = comparison.relative_error();
+                println!(
+                    "Shape:{:?}|Buffer:{}|II:{}|{:?}|{:?}|Coarse error {:.3}",
+                    shape, buffer, ii, tiling, comparison, error
+                );
+                errors.push(error);
+            }
+        }
+        // The PE-level model has no initiation interval, so the coarse one drifts
+        // above it as the interval grows
+        for per_buffer in errors.chunks(3) {
+            assert!(per_buffer[1] > 0.0);
+            assert!(per_buffer.windows(2).all(|e| e[0] < e[1]));
+        }
+        // Deeper buffers delay the first matmul of the coarse node, at full rate
+        // nothing else hides that delay
+        assert!(errors[0] < errors[3] && errors[3] < errors[6]);
+    }
+}
@@ -0,0 +1,78 @@
+use dgemm::{
+    gemm::Dataflow,
+    sweep::{Sweep, SweepSpace},
+    trace::{clean_trace, trace_size},
+    tune::Tuner,
+};
+
+#[test]
+fn tuner_pareto_test() {
+    const SHAPE: [usize; 3] = [32, 16, 16];
+
+    clean_trace();
+    let space = SweepSpace {
+        dims: vec![[2, 2]],
+        link_capacity: vec![2, 4, 8, 16],
+        buffer_size: vec![1, 2, 4, 8],
+        initiation_interval: vec![1],
+        dataflow: vec![Dataflow::Systolic],
+    };
+    let tuner = |target| {
+        Tuner::new(
+            Sweep::new("tune", SHAPE, space.clone()).with_jobs(2),
+            target,
+        )
+    };
+    let reference = tuner(1.0);
+    let report = reference.tune();
+    let front = reference.front(&report);
+    // Every point was simulated once, each into its own trace subdirectory
+    for idx in 0..space.len() {
+        assert!(trace_size(&format!("tune/{idx}")) > 0);
+    }
+    assert!(trace_size(&format!("tune/{}", space.len())) == 0);
+    for pair in front.windows(2) {
+        assert!(pair[0].sram_bytes < pair[1].sram_bytes && pair[0].cycles > pair[1].cycles);
+    }
+    for r in front.iter() {
+        println!(
+            "L:{}|B:{}|SRAM {} bytes|{} cycles|{:.2} MACs/cycle",
+            r.params.link_capacity,
+            r.params.buffer_size,
+            r.sram_bytes,
+            r.cycles,
+            r.macs_per_cycle()
+        );
+    }
+
+    // A target in the middle of the front, the tuner has to land on the front point
+    let target = front[front.len() / 2].macs_per_cycle();
+    let report = tuner(target).tune();
+    let best = report.best.expect("The front reaches the target");
+    let expected = front.iter().find(|r| r.macs_per_cycle() >= target).unwrap();
+    assert_eq!(
+        (best.sram_bytes, best.cycles),
+        (expected.sram_bytes, expected.cycles)
+    );
+    println!(
+        "Target {:.2}|Best L:{} B:{}|Simulated {} of {}",
+        target,
+        best.params.link_capacity,
+        best.params.buffer_size,
+        report.evaluated.len(),
+        space.len()
+    );
+    assert!(report.evaluated.len() + report.pruned <= space.len());
+    assert!(report.evaluated.len() < space.len());
+
+    // Nothing reaches an impossible target
+    let space = SweepSpace {
+        dims: vec![[2, 2]],
+        link_capacity: vec![4],
+        buffer_size: vec![2],
+        initiation_interval: vec![1],
+        dataflow: vec![Dataflow::Systolic],
+    };
+    let report = Tuner::new(Sweep::new("tune_none", SHAPE, space), 1e6).tune();
+    assert!(report.best.is_none() && report.evaluated.is_empty() && report.pruned == 1);
+}